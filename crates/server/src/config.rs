@@ -13,6 +13,27 @@ pub struct PostgresConfig {
     pub ssl_mode: bool,
     pub scheme_name: String,
     pub default_migrations: String,
+    /// Maximum number of simultaneous connections to the database
+    #[serde(default = "default_pool_size")]
+    pub pool_size: usize,
+    /// Number of retries when a connection can't be opened
+    #[serde(default = "default_connect_retries")]
+    pub connect_retries: u32,
+    /// Delay before the first retry, doubled after each failure
+    #[serde(default = "default_connect_backoff_ms")]
+    pub connect_backoff_ms: u64,
+}
+
+fn default_pool_size() -> usize {
+    16
+}
+
+fn default_connect_retries() -> u32 {
+    5
+}
+
+fn default_connect_backoff_ms() -> u64 {
+    500
 }
 
 #[derive(Serialize, Deserialize, Debug, Default, Clone)]
@@ -22,6 +43,80 @@ pub struct EMailerConfig {
     pub smtp_auth: Option<(String, String)>,
 }
 
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SessionConfig {
+    /// Lifetime in seconds of a session without activity
+    pub short_lifetime: i64,
+    /// Lifetime in seconds of a "remember me" session without activity
+    pub remember_me_lifetime: i64,
+    /// Interval in seconds between two purges of the expired tokens and reset codes
+    pub purge_interval: u64,
+}
+
+impl Default for SessionConfig {
+    fn default() -> Self {
+        Self {
+            short_lifetime: 24 * 60 * 60,
+            remember_me_lifetime: 30 * 24 * 60 * 60,
+            purge_interval: 60 * 60,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct EmailVerificationConfig {
+    /// Refuse to log in until the email address is verified
+    pub required_for_login: bool,
+    /// Refuse to create calendars until the email address is verified
+    pub required_for_calendar_creation: bool,
+    /// Minimum delay in seconds between two verification emails
+    pub resend_cooldown: i64,
+    /// Validity duration in seconds of a verification code
+    pub code_lifetime: i64,
+}
+
+impl Default for EmailVerificationConfig {
+    fn default() -> Self {
+        Self {
+            required_for_login: false,
+            required_for_calendar_creation: false,
+            resend_cooldown: 60,
+            code_lifetime: 24 * 60 * 60,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RateLimitConfig {
+    /// Duration in seconds of the window in which requests are counted
+    pub window: u64,
+    /// Maximum number of sensitive requests (login, password reset...) per ip and per window
+    pub max_requests_per_ip: u32,
+    /// Maximum number of sensitive requests per targeted account and per window
+    pub max_requests_per_account: u32,
+    /// Number of failed attempts before an ip or an account is locked
+    pub lockout_threshold: u32,
+    /// Duration in seconds of the first lockout, doubled after each new failure
+    pub lockout_duration: u64,
+    pub max_lockout_duration: u64,
+    /// Number of wrong guesses after which a password reset code is invalidated
+    pub reset_code_max_attempts: i32,
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        Self {
+            window: 60,
+            max_requests_per_ip: 30,
+            max_requests_per_account: 10,
+            lockout_threshold: 5,
+            lockout_duration: 30,
+            max_lockout_duration: 60 * 60,
+            reset_code_max_attempts: 5,
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug, Default, Clone)]
 pub struct WebClientConfig {
     pub client_path: PathBuf,
@@ -41,6 +136,12 @@ pub struct TlsConfig {
 pub struct BackendConfig {
     pub postgres: PostgresConfig,
     pub emailer: EMailerConfig,
+    #[serde(default)]
+    pub sessions: SessionConfig,
+    #[serde(default)]
+    pub email_verification: EmailVerificationConfig,
+    #[serde(default)]
+    pub rate_limit: RateLimitConfig,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -50,6 +151,9 @@ pub struct Config {
     pub web_client_config: WebClientConfig,
     pub tls_config: TlsConfig,
     pub use_tls: bool,
+    /// Read the client address from the X-Forwarded-For header. Only enable it behind a reverse proxy setting this header.
+    #[serde(default)]
+    pub trust_proxy_headers: bool,
 }
 
 impl Default for Config {
@@ -66,12 +170,18 @@ impl Default for Config {
                     ssl_mode: false,
                     scheme_name: "schedulator".to_string(),
                     default_migrations: "./migrations".to_string(),
+                    pool_size: default_pool_size(),
+                    connect_retries: default_connect_retries(),
+                    connect_backoff_ms: default_connect_backoff_ms(),
                 },
                 emailer: EMailerConfig {
                     source_address: "noreply@schedulator.com".to_string(),
                     smtp_server: "mail.schedulator.com".to_string(),
                     smtp_auth: None,
                 },
+                sessions: SessionConfig::default(),
+                email_verification: EmailVerificationConfig::default(),
+                rate_limit: RateLimitConfig::default(),
             },
             web_client_config: WebClientConfig {
                 client_path: PathBuf::from("./webclient"),
@@ -85,6 +195,7 @@ impl Default for Config {
                 private_key: PathBuf::from("/Path/To/private_key.pem"),
             },
             use_tls: true,
+            trust_proxy_headers: false,
         }
     }
 }
@@ -1,31 +1,149 @@
 use anyhow::Error;
 use postgres_from_row::FromRow;
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fmt::Write;
+use std::time::{SystemTime, UNIX_EPOCH};
 use crate::database::Database;
 use crate::{query_fmt, query_object, query_objects};
-use crate::types::database_ids::UserId;
+use crate::types::database_ids::{AuthTokenId, UserId};
 use crate::types::enc_string::EncString;
+use rand::distr::{Alphanumeric, SampleString};
 
+/// Minimum delay in seconds between two renewals of a token, to avoid writing to the database on every request
+const RENEW_INTERVAL: i64 = 60;
 
 #[derive(Serialize, Deserialize, Debug, Default, Clone, FromRow)]
 pub struct AuthToken {
+    id: AuthTokenId,
     pub owner: UserId,
+    pub device: EncString,
+    pub expdate: i64,
+    /// Duration in seconds the token stays valid after its last use
+    pub lifetime: i64,
+    pub created_at: i64,
+    pub last_used: i64,
+    /// Address of the last request made with this token
+    pub ip: Option<String>,
+}
+
+/// A newly created token, with the value the client must send to authenticate
+#[derive(Serialize, Debug)]
+pub struct NewAuthToken {
+    #[serde(flatten)]
+    pub auth_token: AuthToken,
     pub token: EncString,
+}
+
+/// Public information about an authentication token, without the token itself
+#[derive(Serialize, Debug)]
+pub struct Session {
+    pub id: AuthTokenId,
     pub device: EncString,
+    pub created_at: i64,
+    pub last_used: i64,
     pub expdate: i64,
+    pub ip: Option<String>,
+    /// This session is the one used by the request
+    pub current: bool,
+}
+
+impl Session {
+    pub fn new(token: AuthToken, current: Option<&AuthTokenId>) -> Self {
+        Self {
+            current: current == Some(&token.id),
+            id: token.id,
+            device: token.device,
+            created_at: token.created_at,
+            last_used: token.last_used,
+            expdate: token.expdate,
+            ip: token.ip,
+        }
+    }
 }
 
 impl AuthToken {
+    /// Only a hash of the tokens is stored : the token itself is given once to the client
+    pub fn hash(token: &EncString) -> String {
+        let mut hash = String::new();
+        for byte in Sha256::digest(token.encoded().as_bytes()) {
+            let _ = write!(hash, "{byte:02x}");
+        }
+        hash
+    }
+
+    /// Register a new random token for a user
+    pub async fn create(db: &Database, owner: &UserId, device: &EncString, lifetime: i64, ip: Option<String>) -> Result<NewAuthToken, Error> {
+        let mut token: EncString;
+        let mut token_hash: String;
+        loop {
+            token = EncString::encode(Alphanumeric.sample_string(&mut rand::rng(), 64).as_str());
+            token_hash = Self::hash(&token);
+            if query_fmt!(db, "SELECT id FROM SCHEMA_NAME.authtoken WHERE token_hash = $1", token_hash).is_empty() {
+                break;
+            }
+        }
+
+        let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() as i64;
+        let auth_token = query_object!(db, AuthToken, "INSERT INTO SCHEMA_NAME.authtoken (owner, token_hash, device, expdate, lifetime, created_at, last_used, ip) VALUES ($1, $2, $3, $4, $5, $6, $6, $7) RETURNING *",
+            owner, token_hash, device, now + lifetime, lifetime, now, ip)
+            .ok_or(Error::msg("Failed to add authentication token"))?;
+        Ok(NewAuthToken { auth_token, token })
+    }
+
+    /// Find a valid token. Expired tokens are deleted.
     pub async fn find(db: &Database, token: &EncString) -> Result<AuthToken, Error> {
-        query_object!(db, AuthToken, "SELECT * FROM SCHEMA_NAME.authtoken WHERE token = $1", token).ok_or(Error::msg("Invalid authentication token"))
+        let token = query_object!(db, AuthToken, "SELECT * FROM SCHEMA_NAME.authtoken WHERE token_hash = $1", Self::hash(token)).ok_or(Error::msg("Invalid authentication token"))?;
+        if token.expdate < SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() as i64 {
+            AuthToken::delete(&token, db).await?;
+            return Err(Error::msg("Authentication token expired"));
+        }
+        Ok(token)
     }
 
     pub async fn from_user(db: &Database, id: &UserId) -> Result<Vec<AuthToken>, Error> {
-        Ok(query_objects!(db, AuthToken, "SELECT * FROM SCHEMA_NAME.authtoken WHERE owner = $1", id))
+        Ok(query_objects!(db, AuthToken, "SELECT * FROM SCHEMA_NAME.authtoken WHERE owner = $1 ORDER BY last_used DESC", id))
+    }
+
+    pub async fn from_id(db: &Database, owner: &UserId, id: &AuthTokenId) -> Result<AuthToken, Error> {
+        query_object!(db, AuthToken, "SELECT * FROM SCHEMA_NAME.authtoken WHERE owner = $1 AND id = $2", owner, id).ok_or(Error::msg("Session not found"))
+    }
+
+    /// Push back the expiration date of a token that was just used
+    pub async fn renew(&mut self, db: &Database, ip: Option<String>) -> Result<(), Error> {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() as i64;
+        if now + self.lifetime - self.expdate < RENEW_INTERVAL && ip == self.ip {
+            return Ok(());
+        }
+        self.expdate = now + self.lifetime;
+        self.last_used = now;
+        self.ip = ip;
+        query_fmt!(db, "UPDATE SCHEMA_NAME.authtoken SET expdate = $1, last_used = $2, ip = $3 WHERE id = $4", self.expdate, self.last_used, self.ip, self.id);
+        Ok(())
     }
 
     pub async fn delete(token: &AuthToken, db: &Database) -> Result<(), Error> {
-        query_fmt!(db, "DELETE FROM SCHEMA_NAME.authtoken WHERE token = $1", token.token);
+        query_fmt!(db, "DELETE FROM SCHEMA_NAME.authtoken WHERE id = $1", token.id);
         Ok(())
     }
-}
\ No newline at end of file
+
+    /// Remove every token of a user, except the given one
+    pub async fn delete_from_user(db: &Database, owner: &UserId, except: Option<&AuthTokenId>) -> Result<usize, Error> {
+        Ok(match except {
+            None => query_fmt!(db, "DELETE FROM SCHEMA_NAME.authtoken WHERE owner = $1 RETURNING id", owner),
+            Some(except) => query_fmt!(db, "DELETE FROM SCHEMA_NAME.authtoken WHERE owner = $1 AND id != $2 RETURNING id", owner, except),
+        }.len())
+    }
+
+    /// Remove every expired token. Returns the number of removed tokens.
+    pub async fn purge_expired(db: &Database) -> Result<usize, Error> {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() as i64;
+        Ok(query_fmt!(db, "DELETE FROM SCHEMA_NAME.authtoken WHERE expdate < $1 RETURNING id", now).len())
+    }
+}
+
+impl AuthToken {
+    pub fn id(&self) -> &AuthTokenId {
+        &self.id
+    }
+}
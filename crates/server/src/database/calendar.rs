@@ -1,3 +1,6 @@
+use crate::database::calendar_feed::CalendarFeed;
+use crate::database::calendar_members::{CalendarMember, CalendarRole};
+use crate::database::calendar_share_links::CalendarShareLink;
 use crate::database::calendar_users::CalendarUser;
 use crate::database::Database;
 use crate::types::database_ids::{DatabaseIdTrait, CalendarId, UserId};
@@ -22,6 +25,8 @@ pub struct Calendar {
     pub end_daily_hour: i64,
     pub require_account: bool,
     pub default_presence: f32,
+    /// Role of the visitors that are not members of this calendar
+    pub default_role: CalendarRole,
 }
 
 impl Calendar {
@@ -40,39 +45,59 @@ impl Calendar {
     pub async fn from_user(db: &Database, user: &UserId) -> Result<Vec<Self>, Error> {
         Ok(query_objects!(db, Self, "SELECT * FROM SCHEMA_NAME.calendars WHERE owner_id = $1", user))
     }
+    /// Calendars the user owns or is a member of
+    pub async fn from_member(db: &Database, user: &UserId) -> Result<Vec<Self>, Error> {
+        Ok(query_objects!(db, Self, "SELECT * FROM SCHEMA_NAME.calendars WHERE owner_id = $1 OR id IN (SELECT calendar_id FROM SCHEMA_NAME.calendar_members WHERE user_id = $1)", user))
+    }
     pub async fn push(&mut self, db: &Database) -> Result<(), Error> {
         if self.id().is_valid() {
             query_fmt!(db, "INSERT INTO SCHEMA_NAME.calendars
-                        (id, owner_id, title, key, start_date, end_date, time_precision, start_daily_hour, end_daily_hour, require_account, default_presence) VALUES
-                        ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11)
+                        (id, owner_id, title, key, start_date, end_date, time_precision, start_daily_hour, end_daily_hour, require_account, default_presence, default_role) VALUES
+                        ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12)
                         ON CONFLICT(id) DO UPDATE SET
-                        id = $1, owner_id = $2, title = $3, key = $4, start_date = $5, end_date = $6, time_precision = $7, start_daily_hour = $8, end_daily_hour = $9, require_account = $10, default_presence = $11;",
-                self.id(), self.owner_id, self.title, self.key, self.start_date, self.end_date, self.time_precision, self.start_daily_hour, self.end_daily_hour, self.require_account, self.default_presence);
+                        id = $1, owner_id = $2, title = $3, key = $4, start_date = $5, end_date = $6, time_precision = $7, start_daily_hour = $8, end_daily_hour = $9, require_account = $10, default_presence = $11, default_role = $12;",
+                self.id(), self.owner_id, self.title, self.key, self.start_date, self.end_date, self.time_precision, self.start_daily_hour, self.end_daily_hour, self.require_account, self.default_presence, self.default_role);
         } else {
-            loop {
-                self.key = EncString::from(Alphanumeric.sample_string(&mut rand::rng(), 16));
-                if query_object!(db, Self, "SELECT * FROM SCHEMA_NAME.calendars WHERE key = $1", self.key).is_none() {
-                    break;
-                }
-            }
+            self.key = Self::generate_key(db).await?;
             let res = query_object!(db, CalendarId, "INSERT INTO SCHEMA_NAME.calendars
-                        (owner_id, title, key, start_date, end_date, time_precision, start_daily_hour, end_daily_hour, require_account, default_presence) VALUES
-                        ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10) RETURNING id",
-                self.owner_id, self.title, self.key, self.start_date, self.end_date, self.time_precision, self.start_daily_hour, self.end_daily_hour, self.require_account, self.default_presence);
+                        (owner_id, title, key, start_date, end_date, time_precision, start_daily_hour, end_daily_hour, require_account, default_presence, default_role) VALUES
+                        ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11) RETURNING id",
+                self.owner_id, self.title, self.key, self.start_date, self.end_date, self.time_precision, self.start_daily_hour, self.end_daily_hour, self.require_account, self.default_presence, self.default_role);
             if let Some(res) = res {
                 self.id = res;
+                CalendarMember { calendar_id: self.id.clone(), user_id: self.owner_id.clone(), role: CalendarRole::Owner }.push(db).await?;
             }
         }
         Ok(())
     }
 
-    pub async fn delete(&self, db: &Database) -> Result<(), Error> {
-        for user in CalendarUser::from_calendar(db, self.id()).await? {
-            CalendarUser::delete(&user, db).await?;
+    async fn generate_key(db: &Database) -> Result<EncString, Error> {
+        loop {
+            let key = EncString::from(Alphanumeric.sample_string(&mut rand::rng(), 16));
+            if query_object!(db, Self, "SELECT * FROM SCHEMA_NAME.calendars WHERE key = $1", key).is_none() {
+                return Ok(key);
+            }
         }
-        query_fmt!(db, r#"DELETE FROM SCHEMA_NAME.calendars WHERE id = $1;"#, self.id());
+    }
+
+    /// Replace the primary key of the calendar. The previous key stops working.
+    pub async fn rotate_key(&mut self, db: &Database) -> Result<(), Error> {
+        self.key = Self::generate_key(db).await?;
+        query_fmt!(db, "UPDATE SCHEMA_NAME.calendars SET key = $1 WHERE id = $2", self.key, self.id);
         Ok(())
     }
+
+    pub async fn delete(&self, db: &Database) -> Result<(), Error> {
+        let tx = db.transaction().await?;
+        for user in CalendarUser::from_calendar(&tx, self.id()).await? {
+            CalendarUser::delete(&user, &tx).await?;
+        }
+        CalendarFeed::delete_from_calendar(&tx, self.id()).await?;
+        CalendarMember::delete_from_calendar(&tx, self.id()).await?;
+        CalendarShareLink::delete_from_calendar(&tx, self.id()).await?;
+        query_fmt!(tx, r#"DELETE FROM SCHEMA_NAME.calendars WHERE id = $1;"#, self.id());
+        tx.commit().await
+    }
 }
 
 impl Calendar {
@@ -0,0 +1,48 @@
+use crate::database::Database;
+use crate::types::database_ids::CalendarId;
+use crate::types::enc_string::EncString;
+use crate::{query_fmt, query_object};
+use anyhow::Error;
+use postgres_from_row::FromRow;
+use rand::distr::{Alphanumeric, SampleString};
+use serde::{Deserialize, Serialize};
+
+/// Secret token used by calendar applications to poll the ics feed of a calendar without being authenticated
+#[derive(Serialize, Deserialize, Debug, Default, Clone, FromRow)]
+pub struct CalendarFeed {
+    pub calendar_id: CalendarId,
+    pub token: EncString,
+}
+
+impl CalendarFeed {
+    pub async fn from_calendar(db: &Database, id: &CalendarId) -> Result<Option<Self>, Error> {
+        Ok(query_object!(db, CalendarFeed, "SELECT * FROM SCHEMA_NAME.calendar_feeds WHERE calendar_id = $1", id))
+    }
+
+    pub async fn find(db: &Database, id: &CalendarId, token: &EncString) -> Result<Self, Error> {
+        query_object!(db, CalendarFeed, "SELECT * FROM SCHEMA_NAME.calendar_feeds WHERE calendar_id = $1 AND token = $2", id, token).ok_or(Error::msg("Invalid feed token"))
+    }
+
+    /// Create or replace the feed token of a calendar
+    pub async fn generate(db: &Database, id: &CalendarId) -> Result<Self, Error> {
+        let mut token;
+        loop {
+            token = EncString::from(Alphanumeric.sample_string(&mut rand::rng(), 32));
+            if query_fmt!(db, "SELECT token FROM SCHEMA_NAME.calendar_feeds WHERE token = $1", token).is_empty() {
+                break;
+            }
+        }
+        query_fmt!(db, "INSERT INTO SCHEMA_NAME.calendar_feeds
+                        (calendar_id, token) VALUES
+                        ($1, $2)
+                        ON CONFLICT(calendar_id) DO UPDATE SET
+                        token = $2;",
+            id, token);
+        Ok(Self { calendar_id: id.clone(), token })
+    }
+
+    pub async fn delete_from_calendar(db: &Database, id: &CalendarId) -> Result<(), Error> {
+        query_fmt!(db, "DELETE FROM SCHEMA_NAME.calendar_feeds WHERE calendar_id = $1;", id);
+        Ok(())
+    }
+}
@@ -0,0 +1,106 @@
+use crate::database::Database;
+use crate::types::database_ids::{CalendarId, UserId};
+use crate::{query_fmt, query_object, query_objects};
+use anyhow::Error;
+use postgres_from_row::FromRow;
+use serde::{Deserialize, Serialize};
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+
+/// Rights of a user over a calendar. Each role includes the rights of the previous ones.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CalendarRole {
+    /// Can see the calendar and its events
+    Viewer,
+    /// Can add itself to the calendar and edit the events of its own calendar users
+    #[default]
+    Participant,
+    /// Can manage every calendar user and their events
+    Editor,
+    /// Can edit the settings, the members and delete the calendar
+    Owner,
+}
+
+impl Display for CalendarRole {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            CalendarRole::Viewer => "viewer",
+            CalendarRole::Participant => "participant",
+            CalendarRole::Editor => "editor",
+            CalendarRole::Owner => "owner",
+        })
+    }
+}
+
+impl FromStr for CalendarRole {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "viewer" => Ok(CalendarRole::Viewer),
+            "participant" => Ok(CalendarRole::Participant),
+            "editor" => Ok(CalendarRole::Editor),
+            "owner" => Ok(CalendarRole::Owner),
+            _ => Err(Error::msg(format!("Unknown calendar role '{s}'"))),
+        }
+    }
+}
+
+impl postgres_types::ToSql for CalendarRole {
+    fn to_sql(&self, ty: &postgres_types::Type, out: &mut postgres_types::private::BytesMut) -> Result<postgres_types::IsNull, Box<dyn std::error::Error + Sync + Send>> {
+        self.to_string().to_sql(ty, out)
+    }
+    fn accepts(ty: &postgres_types::Type) -> bool { <String>::accepts(ty) }
+    postgres_types::to_sql_checked!();
+}
+impl<'a> postgres_types::FromSql<'a> for CalendarRole {
+    fn from_sql(ty: &postgres_types::Type, raw: &'a [u8]) -> Result<Self, Box<dyn std::error::Error + Sync + Send>> {
+        Ok(CalendarRole::from_str(&<String>::from_sql(ty, raw)?)?)
+    }
+    fn accepts(ty: &postgres_types::Type) -> bool { <String>::accepts(ty) }
+}
+
+/// A user given an explicit role on a calendar
+#[derive(Debug, Default, Clone, FromRow, Serialize, Deserialize)]
+pub struct CalendarMember {
+    pub calendar_id: CalendarId,
+    pub user_id: UserId,
+    pub role: CalendarRole,
+}
+
+impl CalendarMember {
+    pub async fn from_user(db: &Database, calendar: &CalendarId, user: &UserId) -> Result<Option<Self>, Error> {
+        Ok(query_object!(db, Self, "SELECT * FROM SCHEMA_NAME.calendar_members WHERE calendar_id = $1 AND user_id = $2", calendar, user))
+    }
+
+    pub async fn from_calendar(db: &Database, calendar: &CalendarId) -> Result<Vec<Self>, Error> {
+        Ok(query_objects!(db, Self, "SELECT * FROM SCHEMA_NAME.calendar_members WHERE calendar_id = $1", calendar))
+    }
+
+    /// Give a role to a user, replacing its previous one
+    pub async fn push(&self, db: &Database) -> Result<(), Error> {
+        query_fmt!(db, "INSERT INTO SCHEMA_NAME.calendar_members
+                        (calendar_id, user_id, role) VALUES
+                        ($1, $2, $3)
+                        ON CONFLICT(calendar_id, user_id) DO UPDATE SET
+                        role = $3;",
+            self.calendar_id, self.user_id, self.role);
+        Ok(())
+    }
+
+    pub async fn delete(&self, db: &Database) -> Result<(), Error> {
+        query_fmt!(db, "DELETE FROM SCHEMA_NAME.calendar_members WHERE calendar_id = $1 AND user_id = $2;", self.calendar_id, self.user_id);
+        Ok(())
+    }
+
+    pub async fn delete_from_calendar(db: &Database, calendar: &CalendarId) -> Result<(), Error> {
+        query_fmt!(db, "DELETE FROM SCHEMA_NAME.calendar_members WHERE calendar_id = $1;", calendar);
+        Ok(())
+    }
+
+    pub async fn delete_from_user(db: &Database, user: &UserId) -> Result<(), Error> {
+        query_fmt!(db, "DELETE FROM SCHEMA_NAME.calendar_members WHERE user_id = $1;", user);
+        Ok(())
+    }
+}
@@ -0,0 +1,131 @@
+use crate::database::calendar_members::CalendarRole;
+use crate::database::Database;
+use crate::types::database_ids::{CalendarId, PasswordHash, ShareLinkId};
+use crate::types::enc_string::EncString;
+use crate::{query_fmt, query_object, query_objects};
+use anyhow::Error;
+use postgres_from_row::FromRow;
+use rand::distr::{Alphanumeric, SampleString};
+use serde::{Deserialize, Serialize};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// A link giving access to a calendar with a limited role
+#[derive(Serialize, Deserialize, Debug, Default, Clone, FromRow)]
+pub struct CalendarShareLink {
+    id: ShareLinkId,
+    pub calendar_id: CalendarId,
+    /// Public part of the link
+    pub token: EncString,
+    /// Secret given once the password of the link was provided
+    #[serde(skip_serializing)]
+    access_key: EncString,
+    /// Role given by this link : viewer, participant or editor (manage)
+    pub scope: CalendarRole,
+    /// Expiration date in seconds, if any
+    pub expdate: Option<i64>,
+    #[serde(skip_serializing)]
+    password_hash: Option<PasswordHash>,
+    /// Number of times the link was opened
+    pub uses: i64,
+    pub created_at: i64,
+}
+
+impl CalendarShareLink {
+    pub async fn create(
+        db: &Database,
+        calendar: &CalendarId,
+        scope: CalendarRole,
+        expdate: Option<i64>,
+        password: Option<&EncString>,
+    ) -> Result<Self, Error> {
+        let mut token;
+        loop {
+            token = EncString::from(Alphanumeric.sample_string(&mut rand::rng(), 24));
+            if query_fmt!(db, "SELECT id FROM SCHEMA_NAME.calendar_share_links WHERE token = $1", token).is_empty() {
+                break;
+            }
+        }
+        let access_key = EncString::from(Alphanumeric.sample_string(&mut rand::rng(), 32));
+        let password_hash = match password {
+            None => None,
+            Some(password) => Some(PasswordHash::new(password)?),
+        };
+        let created_at = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() as i64;
+        query_object!(db, Self, "INSERT INTO SCHEMA_NAME.calendar_share_links
+                        (calendar_id, token, access_key, scope, expdate, password_hash, created_at) VALUES
+                        ($1, $2, $3, $4, $5, $6, $7) RETURNING *",
+            calendar, token, access_key, scope, expdate, password_hash, created_at)
+            .ok_or(Error::msg("Failed to create share link"))
+    }
+
+    pub async fn from_id(db: &Database, id: &ShareLinkId) -> Result<Self, Error> {
+        query_object!(db, Self, "SELECT * FROM SCHEMA_NAME.calendar_share_links WHERE id = $1", id).ok_or(Error::msg("Share link not found"))
+    }
+
+    /// Find a valid link from its public token
+    pub async fn from_token(db: &Database, token: &EncString) -> Result<Self, Error> {
+        let link = query_object!(db, Self, "SELECT * FROM SCHEMA_NAME.calendar_share_links WHERE token = $1", token)
+            .ok_or(Error::msg("Share link not found"))?;
+        if link.is_expired()? {
+            return Err(Error::msg("This share link has expired"));
+        }
+        Ok(link)
+    }
+
+    /// Find a valid link from the credential sent by a visitor : the token of links without password, or the access key
+    pub async fn from_bearer(db: &Database, bearer: &EncString) -> Result<Self, Error> {
+        let link = query_object!(db, Self, "SELECT * FROM SCHEMA_NAME.calendar_share_links WHERE (token = $1 AND password_hash IS NULL) OR access_key = $1", bearer)
+            .ok_or(Error::msg("Share link not found"))?;
+        if link.is_expired()? {
+            return Err(Error::msg("This share link has expired"));
+        }
+        Ok(link)
+    }
+
+    pub async fn from_calendar(db: &Database, calendar: &CalendarId) -> Result<Vec<Self>, Error> {
+        Ok(query_objects!(db, Self, "SELECT * FROM SCHEMA_NAME.calendar_share_links WHERE calendar_id = $1 ORDER BY created_at", calendar))
+    }
+
+    pub fn is_expired(&self) -> Result<bool, Error> {
+        Ok(match self.expdate {
+            None => false,
+            Some(expdate) => expdate < SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() as i64,
+        })
+    }
+
+    pub fn check_password(&self, password: Option<&EncString>) -> Result<bool, Error> {
+        match (&self.password_hash, password) {
+            (None, _) => Ok(true),
+            (Some(_), None) => Ok(false),
+            (Some(hash), Some(password)) => hash.verify(password),
+        }
+    }
+
+    /// Credential to send with the next requests to be granted the role of this link
+    pub fn bearer(&self) -> &EncString {
+        match self.password_hash {
+            None => &self.token,
+            Some(_) => &self.access_key,
+        }
+    }
+
+    pub async fn increment_uses(&mut self, db: &Database) -> Result<(), Error> {
+        query_fmt!(db, "UPDATE SCHEMA_NAME.calendar_share_links SET uses = uses + 1 WHERE id = $1", self.id);
+        self.uses += 1;
+        Ok(())
+    }
+
+    pub async fn delete(&self, db: &Database) -> Result<(), Error> {
+        query_fmt!(db, "DELETE FROM SCHEMA_NAME.calendar_share_links WHERE id = $1;", self.id);
+        Ok(())
+    }
+
+    pub async fn delete_from_calendar(db: &Database, calendar: &CalendarId) -> Result<(), Error> {
+        query_fmt!(db, "DELETE FROM SCHEMA_NAME.calendar_share_links WHERE calendar_id = $1;", calendar);
+        Ok(())
+    }
+
+    pub fn id(&self) -> &ShareLinkId {
+        &self.id
+    }
+}
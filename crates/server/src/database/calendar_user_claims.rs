@@ -0,0 +1,54 @@
+use crate::database::Database;
+use crate::types::database_ids::{CalendarId, CalendarUserId, UserId};
+use crate::{query_fmt, query_object, query_objects};
+use anyhow::Error;
+use postgres_from_row::FromRow;
+use serde::{Deserialize, Serialize};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// A request of an account to control an anonymous calendar user, waiting for the approval of the calendar owner
+#[derive(Debug, Default, Clone, FromRow, Serialize, Deserialize)]
+pub struct CalendarUserClaim {
+    pub calendar_user_id: CalendarUserId,
+    pub user_id: UserId,
+    pub created_at: i64,
+}
+
+impl CalendarUserClaim {
+    pub async fn create(db: &Database, calendar_user: &CalendarUserId, user: &UserId) -> Result<Self, Error> {
+        let created_at = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() as i64;
+        query_object!(db, Self, "INSERT INTO SCHEMA_NAME.calendar_user_claims
+                        (calendar_user_id, user_id, created_at) VALUES
+                        ($1, $2, $3)
+                        ON CONFLICT(calendar_user_id, user_id) DO UPDATE SET
+                        created_at = $3 RETURNING *",
+            calendar_user, user, created_at)
+            .ok_or(Error::msg("Failed to create claim"))
+    }
+
+    pub async fn find(db: &Database, calendar_user: &CalendarUserId, user: &UserId) -> Result<Self, Error> {
+        query_object!(db, Self, "SELECT * FROM SCHEMA_NAME.calendar_user_claims WHERE calendar_user_id = $1 AND user_id = $2", calendar_user, user)
+            .ok_or(Error::msg("Claim not found"))
+    }
+
+    pub async fn from_calendar(db: &Database, calendar: &CalendarId) -> Result<Vec<Self>, Error> {
+        Ok(query_objects!(db, Self, "SELECT claims.* FROM SCHEMA_NAME.calendar_user_claims claims
+                        JOIN SCHEMA_NAME.calendar_users users ON users.id = claims.calendar_user_id
+                        WHERE users.calendar_id = $1 ORDER BY claims.created_at", calendar))
+    }
+
+    pub async fn delete(&self, db: &Database) -> Result<(), Error> {
+        query_fmt!(db, "DELETE FROM SCHEMA_NAME.calendar_user_claims WHERE calendar_user_id = $1 AND user_id = $2;", self.calendar_user_id, self.user_id);
+        Ok(())
+    }
+
+    pub async fn delete_from_calendar_user(db: &Database, calendar_user: &CalendarUserId) -> Result<(), Error> {
+        query_fmt!(db, "DELETE FROM SCHEMA_NAME.calendar_user_claims WHERE calendar_user_id = $1;", calendar_user);
+        Ok(())
+    }
+
+    pub async fn delete_from_user(db: &Database, user: &UserId) -> Result<(), Error> {
+        query_fmt!(db, "DELETE FROM SCHEMA_NAME.calendar_user_claims WHERE user_id = $1;", user);
+        Ok(())
+    }
+}
@@ -1,13 +1,45 @@
+use crate::database::auth_token::AuthToken;
+use crate::database::calendar_user_claims::CalendarUserClaim;
 use crate::database::Database;
-use crate::types::database_ids::{DatabaseIdTrait, CalendarId, CalendarUserId, UserId};
+use crate::types::database_ids::{DatabaseIdTrait, CalendarId, CalendarUserId, EventId, UserId};
 use crate::{query_fmt, query_object, query_objects};
 use anyhow::Error;
 use postgres_from_row::FromRow;
 use serde::{Deserialize, Serialize};
 use crate::database::event::Event;
 use crate::types::enc_string::EncString;
+use rand::distr::{Alphanumeric, SampleString};
 
-#[derive(Debug, Default, FromRow, Serialize, Deserialize)]
+/// A calendar user that was just created. The claim secret is only given once.
+#[derive(Serialize)]
+pub struct NewCalendarUser {
+    #[serde(flatten)]
+    pub calendar_user: CalendarUser,
+    /// Secret proving the creator of an anonymous calendar user, used to bind it to an account later
+    pub claim_secret: Option<EncString>,
+}
+
+/// What to do when a moved event overlaps an event of the target user coming from the same source
+#[derive(Debug, Default, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum KeepOnConflict {
+    /// Move the event anyway
+    #[default]
+    Both,
+    /// Drop the moved event
+    Target,
+    /// Drop the overlapped events of the target user
+    Source,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct MergeResult {
+    pub target: CalendarUser,
+    pub moved: Vec<Event>,
+    pub deleted: Vec<EventId>,
+}
+
+#[derive(Debug, Default, Clone, FromRow, Serialize, Deserialize)]
 pub struct CalendarUser {
     id: CalendarUserId,
     pub name: EncString,
@@ -32,10 +64,106 @@ impl CalendarUser {
         query_object!(db, Self, "SELECT * FROM SCHEMA_NAME.calendar_users WHERE calendar_id = $1 AND user_id = $2 AND user_id IS NOT NULL", id, user).ok_or(Error::msg("User not found"))
     }
 
+    /// Calendar users that are not bound to an account
+    pub async fn from_anonymous(db: &Database, id: &CalendarId) -> Result<Vec<Self>, Error> {
+        Ok(query_objects!(db, Self, "SELECT * FROM SCHEMA_NAME.calendar_users WHERE calendar_id = $1 AND user_id IS NULL", id))
+    }
+
+    /// Anonymous calendar users of the calendars requiring an account. They were created before the calendar required one.
+    pub async fn anonymous_in_restricted_calendars(db: &Database) -> Result<Vec<Self>, Error> {
+        Ok(query_objects!(db, Self, "SELECT users.* FROM SCHEMA_NAME.calendar_users users
+                        JOIN SCHEMA_NAME.calendars calendars ON calendars.id = users.calendar_id
+                        WHERE calendars.require_account AND users.user_id IS NULL"))
+    }
+
+    /// Generate the secret used to claim this calendar user. Only a hash of it is stored.
+    pub async fn generate_claim_secret(&self, db: &Database) -> Result<EncString, Error> {
+        let secret = EncString::from(Alphanumeric.sample_string(&mut rand::rng(), 32));
+        query_fmt!(db, "UPDATE SCHEMA_NAME.calendar_users SET claim_hash = $1 WHERE id = $2", AuthToken::hash(&secret), self.id);
+        Ok(secret)
+    }
+
+    pub async fn check_claim_secret(&self, db: &Database, secret: &EncString) -> Result<bool, Error> {
+        Ok(!query_fmt!(db, "SELECT id FROM SCHEMA_NAME.calendar_users WHERE id = $1 AND claim_hash = $2", self.id, AuthToken::hash(secret)).is_empty())
+    }
+
+    /// Bind this anonymous calendar user to an account. If the account already controls a calendar user in the same
+    /// calendar, the events are moved to it and this one is removed.
+    /// Returns the calendar user now controlled by the account, and the moved events.
+    pub async fn claim(mut self, db: &Database, account: &UserId) -> Result<MergeResult, Error> {
+        let tx = db.transaction().await?;
+        let result = match Self::from_user(&tx, &self.calendar_id, account).await {
+            Ok(existing) => self.merge_into(&tx, existing, KeepOnConflict::Both).await?,
+            Err(_) => {
+                self.user_id = Some(account.clone());
+                self.push(&tx).await?;
+                query_fmt!(tx, "UPDATE SCHEMA_NAME.calendar_users SET claim_hash = NULL WHERE id = $1", self.id);
+                CalendarUserClaim::delete_from_calendar_user(&tx, &self.id).await?;
+                MergeResult { target: self, ..Default::default() }
+            }
+        };
+        tx.commit().await?;
+        Ok(result)
+    }
+
+    /// Move the events of this calendar user to another one of the same calendar, then remove it.
+    /// The target keeps its name, and gets the account of this user if it has none.
+    pub async fn merge_into(&self, db: &Database, mut target: CalendarUser, keep_on_conflict: KeepOnConflict) -> Result<MergeResult, Error> {
+        if self.calendar_id != target.calendar_id {
+            return Err(Error::msg("Cannot merge users of different calendars"));
+        }
+        if self.id == target.id {
+            return Err(Error::msg("Cannot merge a user into itself"));
+        }
+        if self.user_id.is_some() && target.user_id.is_some() && self.user_id != target.user_id {
+            return Err(Error::msg("Both users are bound to different accounts"));
+        }
+
+        let tx = db.transaction().await?;
+        let target_events = query_objects!(tx, Event, "SELECT * FROM SCHEMA_NAME.events WHERE owner = $1", target.id);
+        let mut result = MergeResult::default();
+        for mut event in query_objects!(tx, Event, "SELECT * FROM SCHEMA_NAME.events WHERE owner = $1", self.id) {
+            let overlapped: Vec<&Event> = target_events.iter().filter(|other| {
+                other.source.encoded() == event.source.encoded() && other.start_time < event.end_time && event.start_time < other.end_time
+            }).collect();
+            match keep_on_conflict {
+                KeepOnConflict::Target if !overlapped.is_empty() => {
+                    event.delete(&tx).await?;
+                    result.deleted.push(event.id().clone());
+                    continue;
+                }
+                KeepOnConflict::Source => {
+                    for other in overlapped {
+                        if !result.deleted.contains(other.id()) {
+                            other.delete(&tx).await?;
+                            result.deleted.push(other.id().clone());
+                        }
+                    }
+                }
+                _ => {}
+            }
+            event.owner = target.id.clone();
+            event.push(&tx).await?;
+            result.moved.push(event);
+        }
+        self.delete(&tx).await?;
+
+        // The source is removed first to keep a single calendar user per account
+        if target.user_id.is_none() && self.user_id.is_some() {
+            target.user_id = self.user_id.clone();
+            target.push(&tx).await?;
+        }
+        tx.commit().await?;
+        result.target = target;
+        Ok(result)
+    }
+
     pub async fn delete(&self, db: &Database) -> Result<(), Error> {
-        Event::delete_from_user(db, &self.id).await?;
-        query_fmt!(db, "DELETE FROM SCHEMA_NAME.calendar_users WHERE id = $1;", self.id);
-        Ok(())
+        let tx = db.transaction().await?;
+        Event::delete_from_user(&tx, &self.id).await?;
+        CalendarUserClaim::delete_from_calendar_user(&tx, &self.id).await?;
+        query_fmt!(tx, "DELETE FROM SCHEMA_NAME.calendar_users WHERE id = $1;", self.id);
+        tx.commit().await
     }
 
     pub async fn push(&mut self, db: &Database) -> Result<(), Error> {
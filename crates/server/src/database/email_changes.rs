@@ -0,0 +1,94 @@
+use crate::config::EMailerConfig;
+use crate::database::email_verifications::EmailVerification;
+use crate::database::user::User;
+use crate::database::Database;
+use crate::emailer::send_email;
+use crate::types::database_ids::UserId;
+use crate::types::enc_string::EncString;
+use crate::{query_fmt, query_object};
+use anyhow::Error;
+use lettre::message::Mailbox;
+use postgres_from_row::FromRow;
+use rand::distr::{Alphanumeric, SampleString};
+use serde::{Deserialize, Serialize};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::info;
+
+/// An email change waiting to be confirmed from the new address
+#[derive(Debug, Default, Clone, FromRow, Serialize, Deserialize)]
+pub struct EmailChange {
+    user_id: UserId,
+    pub new_email: EncString,
+    code: String,
+    expdate: i64,
+}
+
+impl EmailChange {
+    /// Send a confirmation code to the new address. A previous request of the same user is replaced.
+    pub async fn create(db: &Database, config: &EMailerConfig, user: &User, new_email: &EncString) -> Result<(), Error> {
+        let code = Alphanumeric.sample_string(&mut rand::rng(), 8);
+        // Expire in 1h
+        let exp_date = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() as i64 + 60 * 60;
+        query_fmt!(
+            db,
+            "INSERT INTO SCHEMA_NAME.email_changes
+                        (user_id, new_email, code, expdate) VALUES
+                        ($1, $2, $3, $4)
+                        ON CONFLICT(user_id) DO UPDATE SET
+                        new_email = $2, code = $3, expdate = $4;",
+            user.id(),
+            new_email,
+            code,
+            exp_date
+        );
+
+        send_email(
+            config,
+            Mailbox::new(Some(user.display_name.plain()?), new_email.plain()?.parse()?),
+            "Confirm your new Schedulator email",
+            String::from("You have asked to use this address for your Schedulator account."),
+            format!("This confirmation code expire in 1 hour.\n<b>{code}</b>\n\n\nPlease ignore this email if this wasn't you."),
+        )?;
+        info!("Successfully sent email change confirmation to {}", new_email.plain()?);
+        Ok(())
+    }
+
+    /// Apply the pending email change of a user if the code is valid
+    pub async fn confirm(db: &Database, user: &mut User, code: &String) -> Result<(), Error> {
+        let change = query_object!(
+            db,
+            EmailChange,
+            "SELECT * FROM SCHEMA_NAME.email_changes WHERE user_id = $1 AND code = $2",
+            user.id(),
+            code
+        ).ok_or(Error::msg("Invalid code"))?;
+
+        let tx = db.transaction().await?;
+        query_fmt!(tx, "DELETE FROM SCHEMA_NAME.email_changes WHERE user_id = $1;", change.user_id);
+        if change.expdate < SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() as i64 {
+            tx.commit().await?;
+            return Err(Error::msg("Outdated request"));
+        }
+        if User::from_email(&tx, &change.new_email).await.is_ok() {
+            tx.commit().await?;
+            return Err(Error::msg("This email is already used"));
+        }
+        // The new address was verified by receiving the code
+        user.email = change.new_email;
+        user.email_verified = true;
+        User::push(user, &tx).await?;
+        EmailVerification::delete_from_user(&tx, user.id()).await?;
+        tx.commit().await
+    }
+
+    pub async fn delete_from_user(db: &Database, user: &UserId) -> Result<(), Error> {
+        query_fmt!(db, "DELETE FROM SCHEMA_NAME.email_changes WHERE user_id = $1;", user);
+        Ok(())
+    }
+
+    /// Remove every expired request. Returns the number of removed requests.
+    pub async fn purge_expired(db: &Database) -> Result<usize, Error> {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() as i64;
+        Ok(query_fmt!(db, "DELETE FROM SCHEMA_NAME.email_changes WHERE expdate < $1 RETURNING user_id", now).len())
+    }
+}
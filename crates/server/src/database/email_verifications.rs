@@ -0,0 +1,95 @@
+use crate::config::{EMailerConfig, EmailVerificationConfig};
+use crate::database::user::User;
+use crate::database::Database;
+use crate::emailer::send_email;
+use crate::types::database_ids::UserId;
+use crate::{query_fmt, query_object};
+use anyhow::Error;
+use lettre::message::Mailbox;
+use postgres_from_row::FromRow;
+use rand::distr::{Alphanumeric, SampleString};
+use serde::{Deserialize, Serialize};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::info;
+
+#[derive(Debug, Default, Clone, FromRow, Serialize, Deserialize)]
+pub struct EmailVerification {
+    user_id: UserId,
+    code: String,
+    expdate: i64,
+    sent_at: i64,
+}
+
+impl EmailVerification {
+    pub async fn from_user(db: &Database, id: &UserId) -> Result<Option<EmailVerification>, Error> {
+        Ok(query_object!(db, EmailVerification, "SELECT * FROM SCHEMA_NAME.email_verifications WHERE user_id = $1", id))
+    }
+
+    /// Number of seconds to wait before another verification email can be sent to this user
+    pub async fn cooldown(db: &Database, config: &EmailVerificationConfig, id: &UserId) -> Result<i64, Error> {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() as i64;
+        Ok(match Self::from_user(db, id).await? {
+            None => 0,
+            Some(verification) => (verification.sent_at + config.resend_cooldown - now).max(0),
+        })
+    }
+
+    /// Send a new verification code. The previous code of this user is replaced.
+    pub async fn create(db: &Database, emailer: &EMailerConfig, config: &EmailVerificationConfig, user: &User) -> Result<(), Error> {
+        let code = Alphanumeric.sample_string(&mut rand::rng(), 8);
+        let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() as i64;
+        query_fmt!(
+            db,
+            "INSERT INTO SCHEMA_NAME.email_verifications
+                        (user_id, code, expdate, sent_at) VALUES
+                        ($1, $2, $3, $4)
+                        ON CONFLICT(user_id) DO UPDATE SET
+                        code = $2, expdate = $3, sent_at = $4;",
+            user.id(),
+            code,
+            now + config.code_lifetime,
+            now
+        );
+
+        send_email(
+            emailer,
+            Mailbox::new(Some(user.display_name.plain()?), user.email.plain()?.parse()?),
+            "Verify your Schedulator email",
+            String::from("Welcome to Schedulator ! Please verify your email address."),
+            format!("Your verification code :\n<b>{code}</b>\n\n\nPlease ignore this email if you did not create an account."),
+        )?;
+        info!("Successfully sent verification email to {}", user.email.plain()?);
+        Ok(())
+    }
+
+    /// Mark the email of the user as verified if the code is valid
+    pub async fn verify(db: &Database, user: &mut User, code: &String) -> Result<(), Error> {
+        let verification = query_object!(
+            db,
+            EmailVerification,
+            "SELECT * FROM SCHEMA_NAME.email_verifications WHERE user_id = $1 AND code = $2",
+            user.id(),
+            code
+        ).ok_or(Error::msg("Invalid code"))?;
+        if verification.expdate < SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() as i64 {
+            return Err(Error::msg("Outdated request"));
+        }
+
+        let tx = db.transaction().await?;
+        Self::delete_from_user(&tx, user.id()).await?;
+        user.email_verified = true;
+        User::push(user, &tx).await?;
+        tx.commit().await
+    }
+
+    pub async fn delete_from_user(db: &Database, user: &UserId) -> Result<(), Error> {
+        query_fmt!(db, "DELETE FROM SCHEMA_NAME.email_verifications WHERE user_id = $1;", user);
+        Ok(())
+    }
+
+    /// Remove every expired code. Returns the number of removed codes.
+    pub async fn purge_expired(db: &Database) -> Result<usize, Error> {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() as i64;
+        Ok(query_fmt!(db, "DELETE FROM SCHEMA_NAME.email_verifications WHERE expdate < $1 RETURNING user_id", now).len())
+    }
+}
@@ -6,7 +6,7 @@ use anyhow::Error;
 use postgres_from_row::FromRow;
 use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Default, Serialize, Deserialize, FromRow)]
+#[derive(Debug, Default, Clone, Serialize, Deserialize, FromRow)]
 pub struct Event {
     id: EventId,
     pub calendar: CalendarId,
@@ -15,7 +15,16 @@ pub struct Event {
     pub start_time: i64,
     pub end_time: i64,
     pub source: EncString,
-    pub presence: f32
+    pub presence: f32,
+    /// RRULE of recurring events (ex: FREQ=WEEKLY;BYDAY=TU;COUNT=10)
+    #[serde(default)]
+    pub recurrence: Option<String>,
+    /// Start times of the excluded occurrences
+    #[serde(default)]
+    pub exdates: Vec<i64>,
+    /// Time zone of recurring events (ex: Europe/Paris), in which the occurrences keep the local time of the first one. UTC if none.
+    #[serde(default)]
+    pub timezone: Option<String>,
 }
 
 impl Event {
@@ -27,6 +36,13 @@ impl Event {
         Ok(query_objects!(db, Event, "SELECT * FROM SCHEMA_NAME.events WHERE calendar = $1", id))
     }
 
+    /// Events imported from a source. The owner and its events are locked until the end of the transaction, so concurrent
+    /// imports of the same source are applied one after the other.
+    pub async fn from_source_for_update(db: &Database, owner: &CalendarUserId, source: &EncString) -> Result<Vec<Self>, Error> {
+        query_fmt!(db, "SELECT id FROM SCHEMA_NAME.calendar_users WHERE id = $1 FOR UPDATE", owner);
+        Ok(query_objects!(db, Event, "SELECT * FROM SCHEMA_NAME.events WHERE owner = $1 AND source = $2 FOR UPDATE", owner, source))
+    }
+
     pub async fn delete(&self, db: &Database) -> Result<(), Error> {
         query_fmt!(db, r#"DELETE FROM SCHEMA_NAME.events WHERE id = $1;"#, self.id);
         Ok(())
@@ -40,16 +56,16 @@ impl Event {
     pub async fn push(&mut self, db: &Database) -> Result<(), Error> {
         if self.id().is_valid() {
             query_fmt!(db, "INSERT INTO SCHEMA_NAME.events
-                        (id, calendar, title, owner, start_time, end_time, presence) VALUES
-                        ($1, $2, $3, $4, $5, $6, $7, $8)
+                        (id, calendar, title, owner, start_time, end_time, source, presence, recurrence, exdates, timezone) VALUES
+                        ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11)
                         ON CONFLICT(id) DO UPDATE SET
-                        id = $1, calendar = $2, title = $3, owner = $4, start_time = $5, end_time = $6, source = $7, presence = $8;",
-                self.id(), self.calendar, self.title, self.owner, self.start_time, self.end_time, self.source, self.presence);
+                        id = $1, calendar = $2, title = $3, owner = $4, start_time = $5, end_time = $6, source = $7, presence = $8, recurrence = $9, exdates = $10, timezone = $11;",
+                self.id(), self.calendar, self.title, self.owner, self.start_time, self.end_time, self.source, self.presence, self.recurrence, self.exdates, self.timezone);
         } else {
             let res = query_object!(db, EventId, "INSERT INTO SCHEMA_NAME.events
-                        (calendar, title, owner, start_time, end_time, source, presence) VALUES
-                        ($1, $2, $3, $4, $5, $6, $7) RETURNING id",
-                self.calendar, self.title, self.owner, self.start_time, self.end_time, self.source, self.presence);
+                        (calendar, title, owner, start_time, end_time, source, presence, recurrence, exdates, timezone) VALUES
+                        ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10) RETURNING id",
+                self.calendar, self.title, self.owner, self.start_time, self.end_time, self.source, self.presence, self.recurrence, self.exdates, self.timezone);
             if let Some(res) = res {
                 self.id = res;
             }
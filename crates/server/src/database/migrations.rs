@@ -0,0 +1,212 @@
+use crate::database::Database;
+use crate::{query_fmt, query_objects};
+use anyhow::Error;
+use postgres_from_row::FromRow;
+use sha2::{Digest, Sha256};
+use std::fmt::Write;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::{info, warn};
+
+/// A migration file found on disk
+pub struct Migration {
+    /// `<directory>/<file stem>`, for example `1/3_table_users`
+    pub version: String,
+    pub path: PathBuf,
+    pub sql: String,
+    /// sha256 of the file content, before the schema name substitution
+    pub checksum: String,
+    /// Content of the paired `*.down.sql` file reverting this migration
+    pub down: Option<String>,
+}
+
+/// A migration recorded in the schema_migrations table
+#[derive(FromRow, Debug)]
+pub struct AppliedMigration {
+    pub version: String,
+    pub checksum: String,
+    pub applied_at: i64,
+}
+
+pub enum MigrationState {
+    Applied(i64),
+    Pending,
+    /// The file was modified after being applied
+    Changed(i64),
+    /// The migration was applied but its file does not exist anymore
+    Missing(i64),
+}
+
+pub struct MigrationStatus {
+    pub version: String,
+    pub state: MigrationState,
+}
+
+/// Sort files or directories by their numerical prefix (`10_xxx` comes after `9_xxx`)
+fn sort_entries(entries: &mut [PathBuf]) {
+    entries.sort_by(|a, b| {
+        let a = a.file_name().and_then(|name| name.to_str()).unwrap_or_default();
+        let b = b.file_name().and_then(|name| name.to_str()).unwrap_or_default();
+        let a_index = a.split(['_', '.']).next().and_then(|index| i32::from_str(index).ok());
+        let b_index = b.split(['_', '.']).next().and_then(|index| i32::from_str(index).ok());
+        match (a_index, b_index) {
+            (Some(a_index), Some(b_index)) if a_index != b_index => a_index.cmp(&b_index),
+            _ => a.cmp(b),
+        }
+    });
+}
+
+fn checksum(data: &str) -> String {
+    let mut checksum = String::new();
+    for byte in Sha256::digest(data.as_bytes()) {
+        let _ = write!(checksum, "{byte:02x}");
+    }
+    checksum
+}
+
+/// List the migrations of a directory. It either directly contains the `*.sql` files, or a set of sub-directories
+/// containing them (like the `default_migrations` directory).
+pub fn load_migrations(dir: &Path) -> Result<Vec<Migration>, Error> {
+    let mut entries = vec![];
+    for entry in fs::read_dir(dir)? {
+        entries.push(entry?.path());
+    }
+    sort_entries(&mut entries);
+
+    let group = dir.file_name().and_then(|name| name.to_str()).unwrap_or_default().to_string();
+    let mut migrations = vec![];
+    let mut downs = vec![];
+    for path in entries {
+        if path.is_dir() {
+            migrations.append(&mut load_migrations(&path)?);
+        } else if path.extension().and_then(std::ffi::OsStr::to_str) == Some("sql") {
+            let stem = path.file_stem().and_then(|name| name.to_str()).unwrap_or_default();
+            let sql = fs::read_to_string(&path)?;
+            match stem.strip_suffix(".down") {
+                Some(stem) => downs.push((format!("{group}/{stem}"), path, sql)),
+                None => migrations.push(Migration {
+                    version: format!("{group}/{stem}"),
+                    checksum: checksum(&sql),
+                    sql,
+                    path,
+                    down: None,
+                }),
+            }
+        } else {
+            warn!("{} is not a '*.sql' file", path.display());
+        }
+    }
+    for (version, path, sql) in downs {
+        match migrations.iter_mut().find(|migration| migration.version == version) {
+            Some(migration) => migration.down = Some(sql),
+            None => warn!("{} does not match any migration", path.display()),
+        }
+    }
+    Ok(migrations)
+}
+
+impl Database {
+    async fn create_migrations_table(&self) -> Result<(), Error> {
+        self.db().await?.simple_query(&"CREATE SCHEMA IF NOT EXISTS SCHEMA_NAME;
+            CREATE TABLE IF NOT EXISTS SCHEMA_NAME.schema_migrations (
+                version VARCHAR(255) PRIMARY KEY,
+                checksum CHAR(64) NOT NULL,
+                applied_at BIGINT NOT NULL
+            );".replace("SCHEMA_NAME", &self.schema_name)).await?;
+        Ok(())
+    }
+
+    pub async fn applied_migrations(&self) -> Result<Vec<AppliedMigration>, Error> {
+        self.create_migrations_table().await?;
+        Ok(query_objects!(self, AppliedMigration, "SELECT * FROM SCHEMA_NAME.schema_migrations ORDER BY applied_at, version"))
+    }
+
+    /// Compare the migrations of a directory with the ones applied to the database
+    pub async fn migration_status(&self, dir: &Path) -> Result<Vec<MigrationStatus>, Error> {
+        let migrations = load_migrations(dir)?;
+        let applied = self.applied_migrations().await?;
+
+        let mut status = vec![];
+        for migration in &migrations {
+            let state = match applied.iter().find(|applied| applied.version == migration.version) {
+                None => MigrationState::Pending,
+                Some(applied) if applied.checksum != migration.checksum => MigrationState::Changed(applied.applied_at),
+                Some(applied) => MigrationState::Applied(applied.applied_at),
+            };
+            status.push(MigrationStatus { version: migration.version.clone(), state });
+        }
+        for applied in &applied {
+            if !migrations.iter().any(|migration| migration.version == applied.version) {
+                status.push(MigrationStatus { version: applied.version.clone(), state: MigrationState::Missing(applied.applied_at) });
+            }
+        }
+        Ok(status)
+    }
+
+    /// Apply the migrations of a directory that were not applied yet. Each migration runs in its own transaction.
+    /// Fails without applying anything if an already applied migration was modified.
+    /// Returns the versions that were (or would be, with `dry_run`) applied.
+    pub async fn migrate(&self, dir: &Path, dry_run: bool) -> Result<Vec<String>, Error> {
+        let applied = self.applied_migrations().await?;
+        let migrations = load_migrations(dir)?;
+
+        for migration in &migrations {
+            if let Some(applied) = applied.iter().find(|applied| applied.version == migration.version) {
+                if applied.checksum != migration.checksum {
+                    return Err(Error::msg(format!(
+                        "Migration {} was modified after being applied ({})",
+                        migration.version,
+                        migration.path.display()
+                    )));
+                }
+            }
+        }
+
+        let mut done = vec![];
+        for migration in migrations {
+            if applied.iter().any(|applied| applied.version == migration.version) {
+                continue;
+            }
+            if dry_run {
+                info!("Would apply migration {}", migration.version);
+                done.push(migration.version);
+                continue;
+            }
+
+            let tx = self.transaction().await?;
+            tx.db().await?.simple_query(&migration.sql.replace("SCHEMA_NAME", &self.schema_name)).await
+                .map_err(|error| Error::msg(format!("Failed run migration {} : {}", migration.version, error)))?;
+            let applied_at = SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis() as i64;
+            query_fmt!(tx, "INSERT INTO SCHEMA_NAME.schema_migrations (version, checksum, applied_at) VALUES ($1, $2, $3)",
+                migration.version, migration.checksum, applied_at);
+            tx.commit().await?;
+            info!("Successfully executed migration {}", migration.version);
+            done.push(migration.version);
+        }
+        Ok(done)
+    }
+
+    /// Revert the last `count` applied migrations using their `*.down.sql` files, in a single transaction.
+    /// Nothing is reverted if one of them has no down migration.
+    /// Returns the reverted versions.
+    pub async fn rollback(&self, dir: &Path, count: usize) -> Result<Vec<String>, Error> {
+        let migrations = load_migrations(dir)?;
+        let applied = self.applied_migrations().await?;
+
+        let mut reverted = vec![];
+        let tx = self.transaction().await?;
+        for migration in migrations.iter().rev().filter(|migration| applied.iter().any(|applied| applied.version == migration.version)).take(count) {
+            let down = migration.down.as_ref()
+                .ok_or(Error::msg(format!("Migration {} has no down migration", migration.version)))?;
+            tx.db().await?.simple_query(&down.replace("SCHEMA_NAME", &self.schema_name)).await
+                .map_err(|error| Error::msg(format!("Failed to revert migration {} : {}", migration.version, error)))?;
+            query_fmt!(tx, "DELETE FROM SCHEMA_NAME.schema_migrations WHERE version = $1", migration.version);
+            info!("Reverted migration {}", migration.version);
+            reverted.push(migration.version.clone());
+        }
+        tx.commit().await?;
+        Ok(reverted)
+    }
+}
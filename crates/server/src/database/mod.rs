@@ -1,29 +1,39 @@
 use crate::config::BackendConfig;
+use crate::database::pool::{Pool, PoolStatus, PooledClient};
+use crate::database::transaction::Transaction;
 use anyhow::Error;
-use std::fs;
-use std::path::PathBuf;
-use std::str::FromStr;
-use tokio_postgres::{Client};
-use tracing::{error, info, warn};
+use std::ops::Deref;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio_postgres::Client;
+use tracing::info;
 
 pub mod auth_token;
 pub mod calendar;
+pub mod calendar_feed;
+pub mod calendar_members;
+pub mod calendar_share_links;
+pub mod calendar_user_claims;
 pub mod calendar_users;
+pub mod email_changes;
+pub mod email_verifications;
 pub mod event;
+pub mod migrations;
+pub mod pool;
+pub mod transaction;
 pub mod user;
 pub mod reset_passwords;
 
 pub struct Database {
-    db: Client,
+    pool: Pool,
     pub schema_name: String,
+    /// Connection of the transaction this handle belongs to
+    transaction: Option<Arc<tokio::sync::Mutex<PooledClient>>>,
 }
 
-unsafe impl Send for Database {}
-unsafe impl Sync for Database {}
-
 impl Database {
     pub async fn new(config: &BackendConfig) -> Result<Self, Error> {
-        let (db, connection) = tokio_postgres::connect(
+        let pool = Pool::new(
             format!(
                 "host={} port={} user={} password={} dbname={}",
                 config.postgres.url,
@@ -31,134 +41,112 @@ impl Database {
                 config.postgres.username,
                 config.postgres.secret,
                 config.postgres.database,
-            )
-            .as_str(),
-            tokio_postgres::NoTls,
-        )
-        .await
-        .or_else(|error| {
-            Err(Error::msg(format!(
+            ),
+            config.postgres.pool_size,
+            config.postgres.connect_retries,
+            Duration::from_millis(config.postgres.connect_backoff_ms),
+        );
+
+        // Open a first connection to ensure the configuration is valid
+        let db = pool.get().await.map_err(|error| {
+            Error::msg(format!(
                 "Failed to connect to postgres database postgres://{}@{}:{}-{} : {}",
                 config.postgres.username,
                 config.postgres.url,
                 config.postgres.port,
                 config.postgres.database,
                 error
-            )))
+            ))
         })?;
 
-        tokio::spawn(async move {
-            if let Err(e) = connection.await {
-                error!("Postgres database connection error: {}", e)
-            }
-        });
-
         info!(
-            "Connected to postgres database postgres://{}@{}:{}-{}",
+            "Connected to postgres database postgres://{}@{}:{}-{} (pool size : {})",
             config.postgres.username,
             config.postgres.url,
             config.postgres.port,
-            config.postgres.database
+            config.postgres.database,
+            config.postgres.pool_size
         );
 
-        let mut db_init = false;
-        if !db.query("SELECT schema_name FROM information_schema.schemata WHERE lower(schema_name) = lower($1)", &[&config.postgres.scheme_name]).await?.is_empty() {
-            db_init = true;
-        }
+        drop(db);
 
-        let database = Self {
-            db,
+        Ok(Self {
+            pool,
             schema_name: config.postgres.scheme_name.to_string(),
-        };
-
-        if !db_init {
-            warn!("Database is not initialized !");
-            let mut entries = vec![];
-            for entry in fs::read_dir(PathBuf::from(&config.postgres.default_migrations))? {
-                entries.push(entry?);
-            }
-            entries.sort_by(|a, b| { a.path().cmp(&b.path()) });
-            for entry in entries {
-                database.migrate(entry.path(), &config.postgres.scheme_name).await?;
-            }
-        }
-
-        Ok(database)
+            transaction: None,
+        })
     }
 
-    pub async fn migrate(&self, migrations_dir: PathBuf, schema_name: &str) -> Result<(), Error> {
-        let mut entries = vec![];
-        for entry in fs::read_dir(migrations_dir)? {
-            entries.push(entry?);
+    /// Get a connection from the pool, or the connection of the current transaction
+    pub async fn db(&self) -> Result<DatabaseClient<'_>, Error> {
+        match &self.transaction {
+            None => Ok(DatabaseClient::Pooled(self.pool.get().await?)),
+            Some(transaction) => Ok(DatabaseClient::Transaction(transaction.lock().await)),
         }
+    }
 
-        entries.sort_by(|a, b| {
-            let a = a.file_name();
-            let b = b.file_name();
-            let mut a_split = a.to_str().unwrap().split("_");
-            let mut b_split = b.to_str().unwrap().split("_");
-
-            if let Some(a) = a_split.next() {
-                if let Some(b) = b_split.next() {
-                    if let Ok(a) = i32::from_str(a) {
-                        if let Ok(b) = i32::from_str(b) {
-                            return a.cmp(&b);
-                        }
-                    }
-                }
-            }
-            a.cmp(&b)
-        });
-
-        for entry in entries {
-            let path = entry.path();
-            if path.is_file() && path.extension().and_then(std::ffi::OsStr::to_str) == Some("sql") {
-                let sql = fs::read_to_string(path)?.replace("SCHEMA_NAME", schema_name);
-
-                match self.db.simple_query(&sql).await {
-                    Ok(_) => {
-                        info!(
-                            "Successfully executed migrations {}",
-                            entry.file_name().to_str().unwrap()
-                        );
-                    }
-                    Err(error) => {
-                        return Err(Error::msg(format!(
-                            "Failed run migration migrate {} : {}",
-                            entry.file_name().to_str().unwrap(),
-                            error
-                        )));
-                    }
-                };
-            } else {
-                warn!("{} is not a '*.sql' file", entry.file_name().to_str().unwrap());
-            }
+    /// Start a transaction. Every query made through the returned handle is part of it, and is rolled back unless
+    /// [`Transaction::commit`] is called. Starting a transaction from a transaction handle joins the existing one.
+    pub async fn transaction(&self) -> Result<Transaction, Error> {
+        if let Some(transaction) = &self.transaction {
+            return Ok(Transaction::nested(Self {
+                pool: self.pool.clone(),
+                schema_name: self.schema_name.clone(),
+                transaction: Some(transaction.clone()),
+            }));
         }
+        let client = self.pool.get().await?;
+        client.simple_query("BEGIN").await?;
+        let connection = Arc::new(tokio::sync::Mutex::new(client));
+        Ok(Transaction::new(Self {
+            pool: self.pool.clone(),
+            schema_name: self.schema_name.clone(),
+            transaction: Some(connection.clone()),
+        }, connection))
+    }
 
-        Ok(())
+    /// Ensure the database can still be reached
+    pub async fn health_check(&self) -> Result<PoolStatus, Error> {
+        self.db().await?.simple_query("SELECT 1").await?;
+        Ok(self.pool.status())
     }
+}
+
+/// Connection used to run a single query
+pub enum DatabaseClient<'a> {
+    Pooled(PooledClient),
+    Transaction(tokio::sync::MutexGuard<'a, PooledClient>),
+}
 
-    pub fn db(&self) -> &Client {
-        &self.db
+impl Deref for DatabaseClient<'_> {
+    type Target = Client;
+
+    fn deref(&self) -> &Self::Target {
+        match self {
+            DatabaseClient::Pooled(client) => client,
+            DatabaseClient::Transaction(client) => client,
+        }
     }
 }
 
 #[macro_export]
 macro_rules! query_fmt {
     ($db:expr, $query:expr) => {{
-        $db.db().query(&$query.replace("SCHEMA_NAME", &$db.schema_name), &[]).await?
+        let rows = $db.db().await?.query(&$query.replace("SCHEMA_NAME", &$db.schema_name), &[]).await?;
+        rows
     }};
 
     ($db:expr, $query:expr, $( $bound_values:expr),*) => {{
         let params: &[&(dyn postgres_types::ToSql + Sync)] = &[$(&$bound_values,)*];
-        $db.db().query(&$query.replace("SCHEMA_NAME", &$db.schema_name), params).await?
+        let rows = $db.db().await?.query(&$query.replace("SCHEMA_NAME", &$db.schema_name), params).await?;
+        rows
     }};
 }
 
 #[macro_export]
 macro_rules! query_objects {
     ($db:expr, $StructType:ty, $query:expr) => {{
-        let query = $db.db().query(&$query.replace("SCHEMA_NAME", &$db.schema_name), &[]).await?;
+        let query = $db.db().await?.query(&$query.replace("SCHEMA_NAME", &$db.schema_name), &[]).await?;
         let mut rows = Vec::with_capacity(query.len());
         for row in query {
             rows.push(<$StructType>::try_from_row(&row)?);
@@ -167,7 +155,7 @@ macro_rules! query_objects {
     }};
     ($db:expr, $StructType:ty, $query:expr, $( $bound_values:expr),*) => {{
         let params: &[&(dyn postgres_types::ToSql + Sync)] = &[$(&$bound_values,)*];
-        let query = $db.db().query(&$query.replace("SCHEMA_NAME", &$db.schema_name), params).await?;
+        let query = $db.db().await?.query(&$query.replace("SCHEMA_NAME", &$db.schema_name), params).await?;
         let mut rows = Vec::with_capacity(query.len());
         for row in query {
             rows.push(<$StructType>::try_from_row(&row)?);
@@ -179,7 +167,7 @@ macro_rules! query_objects {
 #[macro_export]
 macro_rules! query_object {
     ($db:expr, $StructType:ty, $query:expr) => {{
-        let mut query = $db.db().query(&$query.replace("SCHEMA_NAME", &$db.schema_name), &[]).await?;
+        let mut query = $db.db().await?.query(&$query.replace("SCHEMA_NAME", &$db.schema_name), &[]).await?;
         if query.len() > 1 {
             return Err(Error::msg("Received more than one expected item"))
         }
@@ -190,7 +178,7 @@ macro_rules! query_object {
     }};
     ($db:expr, $StructType:ty, $query:expr, $( $bound_values:expr),*) => {{
         let params: &[&(dyn postgres_types::ToSql + Sync)] = &[$(&$bound_values,)*];
-        let mut query = $db.db().query(&$query.replace("SCHEMA_NAME", &$db.schema_name), params).await?;
+        let mut query = $db.db().await?.query(&$query.replace("SCHEMA_NAME", &$db.schema_name), params).await?;
         if query.len() > 1 {
             return Err(Error::msg("Received more than one expected item"))
         }
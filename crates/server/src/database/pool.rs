@@ -0,0 +1,143 @@
+use anyhow::Error;
+use serde::Serialize;
+use std::ops::{Deref, DerefMut};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tokio_postgres::Client;
+use tracing::{error, warn};
+
+/// Maximum delay between two connection attempts
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+/// A fixed size set of postgres connections. Connections that were closed (server restart, network failure...) are
+/// dropped when they are given back and a new one is opened on the next request.
+#[derive(Clone)]
+pub struct Pool {
+    inner: Arc<PoolInner>,
+}
+
+struct PoolInner {
+    connection_string: String,
+    idle: Mutex<Vec<Client>>,
+    permits: Arc<Semaphore>,
+    size: usize,
+    connect_retries: u32,
+    connect_backoff: Duration,
+}
+
+#[derive(Serialize, Debug)]
+pub struct PoolStatus {
+    pub size: usize,
+    pub idle: usize,
+    pub in_use: usize,
+}
+
+impl Pool {
+    pub fn new(connection_string: String, size: usize, connect_retries: u32, connect_backoff: Duration) -> Self {
+        let size = size.max(1);
+        Self {
+            inner: Arc::new(PoolInner {
+                connection_string,
+                idle: Mutex::new(Vec::with_capacity(size)),
+                permits: Arc::new(Semaphore::new(size)),
+                size,
+                connect_retries,
+                connect_backoff,
+            }),
+        }
+    }
+
+    /// Wait for an available connection. A new connection is opened if no idle one is alive.
+    pub async fn get(&self) -> Result<PooledClient, Error> {
+        let permit = self.inner.permits.clone().acquire_owned().await?;
+        loop {
+            let client = match self.inner.idle.lock() {
+                Ok(mut idle) => idle.pop(),
+                Err(err) => return Err(Error::msg(format!("Database pool lock is poisoned : {err}"))),
+            };
+            match client {
+                Some(client) if client.is_closed() => {
+                    warn!("Dropped a closed postgres connection");
+                }
+                Some(client) => {
+                    return Ok(PooledClient { client: Some(client), pool: self.inner.clone(), _permit: permit });
+                }
+                None => break,
+            }
+        }
+        let client = self.connect().await?;
+        Ok(PooledClient { client: Some(client), pool: self.inner.clone(), _permit: permit })
+    }
+
+    pub fn status(&self) -> PoolStatus {
+        let in_use = self.inner.size - self.inner.permits.available_permits();
+        PoolStatus {
+            size: self.inner.size,
+            idle: self.inner.idle.lock().map(|idle| idle.len()).unwrap_or_default(),
+            in_use,
+        }
+    }
+
+    /// Open a new connection, retrying with an exponential backoff
+    async fn connect(&self) -> Result<Client, Error> {
+        let pool = &self.inner;
+        let mut delay = pool.connect_backoff;
+        let mut attempt = 0;
+        loop {
+            match tokio_postgres::connect(pool.connection_string.as_str(), tokio_postgres::NoTls).await {
+                Ok((client, connection)) => {
+                    tokio::spawn(async move {
+                        if let Err(e) = connection.await {
+                            error!("Postgres database connection error: {}", e)
+                        }
+                    });
+                    return Ok(client);
+                }
+                Err(error) => {
+                    if attempt >= pool.connect_retries {
+                        return Err(Error::msg(format!("Failed to connect to postgres database : {error}")));
+                    }
+                    attempt += 1;
+                    warn!("Failed to connect to postgres database ({error}), retrying in {}ms ({attempt}/{})", delay.as_millis(), pool.connect_retries);
+                    tokio::time::sleep(delay).await;
+                    delay = (delay * 2).min(MAX_BACKOFF);
+                }
+            }
+        }
+    }
+}
+
+/// A connection borrowed from the pool. It is given back to the pool when dropped.
+pub struct PooledClient {
+    client: Option<Client>,
+    pool: Arc<PoolInner>,
+    _permit: OwnedSemaphorePermit,
+}
+
+impl Deref for PooledClient {
+    type Target = Client;
+
+    fn deref(&self) -> &Self::Target {
+        self.client.as_ref().expect("Pooled client was already released")
+    }
+}
+
+impl DerefMut for PooledClient {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.client.as_mut().expect("Pooled client was already released")
+    }
+}
+
+impl Drop for PooledClient {
+    fn drop(&mut self) {
+        if let Some(client) = self.client.take() {
+            if client.is_closed() {
+                return;
+            }
+            if let Ok(mut idle) = self.pool.idle.lock() {
+                idle.push(client);
+            }
+        }
+    }
+}
@@ -1,3 +1,4 @@
+use crate::database::auth_token::AuthToken;
 use crate::database::user::User;
 use crate::database::Database;
 use crate::types::database_ids::{PasswordHash, UserId};
@@ -8,31 +9,33 @@ use postgres_from_row::FromRow;
 use rand::distr::{Alphanumeric, SampleString};
 use serde::{Deserialize, Serialize};
 use std::time::{SystemTime, UNIX_EPOCH};
-use lettre::{Message, SmtpTransport, Transport};
-use lettre::message::{Mailbox, MultiPart};
-use lettre::transport::smtp::authentication::Credentials;
+use lettre::message::Mailbox;
 use tracing::info;
 use crate::config::EMailerConfig;
+use crate::emailer::send_email;
 
 #[derive(Debug, Default, Clone, FromRow, Serialize, Deserialize)]
 pub struct ResetPasswords {
     user_id: UserId,
     code: String,
     expdate: i64,
+    /// Number of wrong codes submitted for this request
+    attempts: i32,
 }
 
 impl ResetPasswords {
+    /// Find the request of a user with the given code. The request is invalidated after `max_attempts` wrong codes.
     pub async fn from_user(
         db: &Database,
         id: &UserId,
         code: &String,
+        max_attempts: i32,
     ) -> Result<ResetPasswords, Error> {
         match query_object!(
             db,
             ResetPasswords,
-            "SELECT * FROM SCHEMA_NAME.resetpasswords WHERE user_id = $1 AND code = $2",
-            id,
-            code
+            "SELECT * FROM SCHEMA_NAME.resetpasswords WHERE user_id = $1",
+            id
         ) {
             None => Err(Error::msg("This user have not requested a password change")),
             Some(reset_password) => {
@@ -46,11 +49,38 @@ impl ResetPasswords {
                     );
                     return Err(Error::msg("Outdated request"));
                 }
+                if reset_password.code != *code {
+                    if reset_password.attempts + 1 >= max_attempts {
+                        query_fmt!(
+                            db,
+                            r#"DELETE FROM SCHEMA_NAME.resetpasswords WHERE user_id = $1;"#,
+                            reset_password.user_id
+                        );
+                        return Err(Error::msg("Too many invalid codes : please request a new one"));
+                    }
+                    query_fmt!(
+                        db,
+                        r#"UPDATE SCHEMA_NAME.resetpasswords SET attempts = attempts + 1 WHERE user_id = $1;"#,
+                        reset_password.user_id
+                    );
+                    return Err(Error::msg("Invalid code"));
+                }
                 Ok(reset_password)
             }
         }
     }
 
+    pub async fn delete_from_user(db: &Database, user: &UserId) -> Result<(), Error> {
+        query_fmt!(db, r#"DELETE FROM SCHEMA_NAME.resetpasswords WHERE user_id = $1;"#, user);
+        Ok(())
+    }
+
+    /// Remove every expired reset code. Returns the number of removed codes.
+    pub async fn purge_expired(db: &Database) -> Result<usize, Error> {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() as i64;
+        Ok(query_fmt!(db, "DELETE FROM SCHEMA_NAME.resetpasswords WHERE expdate < $1 RETURNING user_id", now).len())
+    }
+
     pub async fn create(db: &Database, config: &EMailerConfig, id: &UserId) -> Result<(), Error> {
         let user = User::from_id(db, id).await?;
         let code = Alphanumeric.sample_string(&mut rand::rng(), 8);
@@ -62,7 +92,7 @@ impl ResetPasswords {
                         (user_id, code, expdate) VALUES
                         ($1, $2, $3)
                         ON CONFLICT(user_id) DO UPDATE SET
-                        code = $2, expdate = $3;",
+                        code = $2, expdate = $3, attempts = 0;",
             id,
             code,
             exp_date
@@ -70,32 +100,18 @@ impl ResetPasswords {
         let username = user.display_name.plain()?;
 
 
-        let email = Message::builder()
-            .from(Mailbox::new(Some("Schedulator".to_string()), config.source_address.parse()?))
-            .to(Mailbox::new(Some(username.clone()), user.email.plain()?.parse()?))
-            .subject("Reset Schedulator password")
-            .multipart(MultiPart::alternative_plain_html(
-                String::from("You have asked for a password reinitialization."),
-                String::from(format!("This reset code expire in 15 minutes.\n<b>{code}</b>\n\n\nPlease inform us if this wasn't you.")),
-            ))?;
-
-        // Open a remote connection to gmail
-        let mut builder = SmtpTransport::relay(&config.smtp_server)?;
-        if let Some((login, password)) = &config.smtp_auth {
-            builder = builder.credentials(Credentials::new(login.clone(), password.clone()));
-        }
-        builder.build().send(&email)?;
+        send_email(
+            config,
+            Mailbox::new(Some(username.clone()), user.email.plain()?.parse()?),
+            "Reset Schedulator password",
+            String::from("You have asked for a password reinitialization."),
+            format!("This reset code expire in 15 minutes.\n<b>{code}</b>\n\n\nPlease inform us if this wasn't you."),
+        )?;
         info!("Successfully sent reset password email to {}", user.email.plain()?);
         Ok(())
     }
 
     pub async fn reset_password(&self, db: &Database, password: &EncString) -> Result<(), Error> {
-        query_fmt!(
-            db,
-            r#"DELETE FROM SCHEMA_NAME.resetpasswords WHERE user_id = $1;"#,
-            self.user_id
-        );
-
         if self.expdate < SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() as i64 {
             query_fmt!(
                 db,
@@ -105,8 +121,17 @@ impl ResetPasswords {
             return Err(Error::msg("Outdated request"));
         }
 
-        let mut user = User::from_id(db, &self.user_id).await?;
-        User::create_or_reset_password(&mut user, db, &PasswordHash::new(password)?).await?;
-        Ok(())
+        // The code is only consumed if the password was successfully updated
+        let tx = db.transaction().await?;
+        query_fmt!(
+            tx,
+            r#"DELETE FROM SCHEMA_NAME.resetpasswords WHERE user_id = $1;"#,
+            self.user_id
+        );
+        let mut user = User::from_id(&tx, &self.user_id).await?;
+        User::create_or_reset_password(&mut user, &tx, &PasswordHash::new(password)?).await?;
+        // Sessions opened with the previous password are closed
+        AuthToken::delete_from_user(&tx, user.id(), None).await?;
+        tx.commit().await
     }
 }
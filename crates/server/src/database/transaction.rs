@@ -0,0 +1,60 @@
+use crate::database::pool::PooledClient;
+use crate::database::Database;
+use anyhow::Error;
+use std::ops::Deref;
+use std::sync::Arc;
+use tracing::error;
+
+/// A database handle bound to a transaction.
+///
+/// It can be used everywhere a [`Database`] is expected. Nothing is written until [`Transaction::commit`] is called :
+/// dropping it (for example when returning early with `?`) rolls back every modification.
+pub struct Transaction {
+    db: Database,
+    /// Connection owning the transaction. None when this handle joined an outer transaction.
+    connection: Option<Arc<tokio::sync::Mutex<PooledClient>>>,
+    done: bool,
+}
+
+impl Transaction {
+    pub(super) fn new(db: Database, connection: Arc<tokio::sync::Mutex<PooledClient>>) -> Self {
+        Self { db, connection: Some(connection), done: false }
+    }
+
+    pub(super) fn nested(db: Database) -> Self {
+        Self { db, connection: None, done: false }
+    }
+
+    /// Commit the transaction. Nested transactions are only committed with the outermost one.
+    pub async fn commit(mut self) -> Result<(), Error> {
+        self.done = true;
+        if let Some(connection) = &self.connection {
+            connection.lock().await.simple_query("COMMIT").await?;
+        }
+        Ok(())
+    }
+}
+
+impl Deref for Transaction {
+    type Target = Database;
+
+    fn deref(&self) -> &Self::Target {
+        &self.db
+    }
+}
+
+impl Drop for Transaction {
+    fn drop(&mut self) {
+        if self.done {
+            return;
+        }
+        if let Some(connection) = self.connection.take() {
+            // The connection is only given back to the pool once the rollback is done
+            tokio::spawn(async move {
+                if let Err(err) = connection.lock().await.simple_query("ROLLBACK").await {
+                    error!("Failed to rollback transaction : {err}");
+                }
+            });
+        }
+    }
+}
@@ -1,18 +1,21 @@
-use crate::database::auth_token::AuthToken;
+use crate::database::auth_token::{AuthToken, NewAuthToken};
 use crate::database::calendar::Calendar;
+use crate::database::calendar_members::CalendarMember;
+use crate::database::calendar_user_claims::CalendarUserClaim;
+use crate::database::email_changes::EmailChange;
+use crate::database::email_verifications::EmailVerification;
+use crate::database::reset_passwords::ResetPasswords;
 use crate::database::Database;
 use crate::types::database_ids::{DatabaseId, DatabaseIdTrait, PasswordHash, UserId};
 use crate::types::enc_string::EncString;
 use crate::{query_fmt, query_object, query_objects};
 use anyhow::Error;
 use postgres_from_row::FromRow;
-use rand::distr::{Alphanumeric, SampleString};
 use rand::random;
 use serde::de::{MapAccess, Visitor};
 use serde::ser::SerializeStruct;
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use std::fmt::Formatter;
-use std::time::{SystemTime, UNIX_EPOCH};
 
 #[derive(Debug, Default, Clone, FromRow)]
 pub struct User {
@@ -20,6 +23,7 @@ pub struct User {
     pub email: EncString,
     pub display_name: EncString,
     password_hash: PasswordHash,
+    pub email_verified: bool,
 }
 
 impl User {
@@ -104,7 +108,7 @@ impl User {
         match query_object!(
             db,
             User,
-            "SELECT * FROM SCHEMA_NAME.users WHERE LOWER(name) = LOWER($1)",
+            "SELECT * FROM SCHEMA_NAME.users WHERE LOWER(display_name) = LOWER($1)",
             name
         ) {
             None => Err(Error::msg("User not found")),
@@ -112,6 +116,15 @@ impl User {
         }
     }
 
+    pub async fn from_email(db: &Database, email: &EncString) -> Result<User, Error> {
+        query_object!(db, User, "SELECT * FROM SCHEMA_NAME.users WHERE LOWER(email) = LOWER($1)", email)
+            .ok_or(Error::msg("User not found"))
+    }
+
+    pub fn check_password(&self, password: &EncString) -> Result<bool, Error> {
+        self.password_hash.verify(password)
+    }
+
     pub async fn exists(
         db: &Database,
         display_name: &EncString,
@@ -161,41 +174,21 @@ impl User {
         }
     }
 
-    pub async fn from_auth_token(db: &Database, authtoken: &EncString) -> Result<User, Error> {
-        User::from_id(db, &AuthToken::find(db, authtoken).await?.owner).await
+    /// Find the owner of a valid token. The token expiration date is pushed back.
+    pub async fn from_auth_token(db: &Database, authtoken: &EncString, ip: Option<String>) -> Result<(User, AuthToken), Error> {
+        let mut token = AuthToken::find(db, authtoken).await?;
+        token.renew(db, ip).await?;
+        Ok((User::from_id(db, &token.owner).await?, token))
     }
 
     pub async fn generate_auth_token(
         user: &User,
         db: &Database,
         device: &EncString,
-    ) -> Result<AuthToken, Error> {
-        let mut token: String;
-        loop {
-            token = Alphanumeric.sample_string(&mut rand::rng(), 64);
-            if query_fmt!(
-                db,
-                "SELECT token FROM SCHEMA_NAME.authtoken WHERE token = $1",
-                token
-            )
-            .is_empty()
-            {
-                break;
-            }
-        }
-        let enc_token = EncString::encode(token.as_str());
-
-        //@TODO : set a valid exp date
-        let exp_date = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() as i64;
-
-        query_fmt!(db, "INSERT INTO SCHEMA_NAME.authtoken (owner, token, device, expdate) VALUES ($1, $2, $3, $4)", user.id(), enc_token, device, exp_date);
-        query_object!(
-            db,
-            AuthToken,
-            "SELECT * from SCHEMA_NAME.authtoken WHERE token = $1",
-            enc_token
-        )
-        .ok_or(Error::msg("Failed to add authentication token"))
+        lifetime: i64,
+        ip: Option<String>,
+    ) -> Result<NewAuthToken, Error> {
+        AuthToken::create(db, user.id(), device, lifetime, ip).await
     }
 
     pub async fn create_or_reset_password(
@@ -228,30 +221,35 @@ impl User {
         query_fmt!(
             db,
             "INSERT INTO SCHEMA_NAME.users
-                        (id, email, password_hash, display_name) VALUES
-                        ($1, $2, $3, $4)
+                        (id, email, password_hash, display_name, email_verified) VALUES
+                        ($1, $2, $3, $4, $5)
                         ON CONFLICT(id) DO UPDATE SET
-                        id = $1, email = $2, password_hash = $3, display_name = $4;",
+                        id = $1, email = $2, password_hash = $3, display_name = $4, email_verified = $5;",
             user.id(),
             user.email,
             user.password_hash,
-            user.display_name
+            user.display_name,
+            user.email_verified
         );
         Ok(())
     }
 
     pub async fn delete(user: &User, db: &Database) -> Result<(), Error> {
-        for repository in Calendar::from_user(db, &user.id()).await? {
-            Calendar::delete(&repository, db).await?;
-        }
-        for token in AuthToken::from_user(db, user.id()).await? {
-            AuthToken::delete(&token, db).await?;
+        let tx = db.transaction().await?;
+        for repository in Calendar::from_user(&tx, &user.id()).await? {
+            Calendar::delete(&repository, &tx).await?;
         }
+        CalendarMember::delete_from_user(&tx, user.id()).await?;
+        CalendarUserClaim::delete_from_user(&tx, user.id()).await?;
+        AuthToken::delete_from_user(&tx, user.id(), None).await?;
+        ResetPasswords::delete_from_user(&tx, user.id()).await?;
+        EmailChange::delete_from_user(&tx, user.id()).await?;
+        EmailVerification::delete_from_user(&tx, user.id()).await?;
         query_fmt!(
-            db,
+            tx,
             r#"DELETE FROM SCHEMA_NAME.users WHERE id = $1;"#,
             user.id()
         );
-        Ok(())
+        tx.commit().await
     }
 }
@@ -0,0 +1,22 @@
+use crate::config::EMailerConfig;
+use anyhow::Error;
+use lettre::message::{Mailbox, MultiPart};
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{Message, SmtpTransport, Transport};
+
+/// Send an email with both a plain text and an html body
+pub fn send_email(config: &EMailerConfig, to: Mailbox, subject: &str, plain: String, html: String) -> Result<(), Error> {
+    let email = Message::builder()
+        .from(Mailbox::new(Some("Schedulator".to_string()), config.source_address.parse()?))
+        .to(to)
+        .subject(subject)
+        .multipart(MultiPart::alternative_plain_html(plain, html))?;
+
+    // Open a remote connection to gmail
+    let mut builder = SmtpTransport::relay(&config.smtp_server)?;
+    if let Some((login, password)) = &config.smtp_auth {
+        builder = builder.credentials(Credentials::new(login.clone(), password.clone()));
+    }
+    builder.build().send(&email)?;
+    Ok(())
+}
@@ -0,0 +1,327 @@
+use anyhow::Error;
+use chrono::{DateTime, NaiveDate, NaiveDateTime, TimeZone, Utc};
+use chrono_tz::Tz;
+use std::collections::HashMap;
+use std::str::FromStr;
+
+/// A single property line of an ics file : NAME;PARAM=value:VALUE
+#[derive(Debug, Clone)]
+pub struct IcsProperty {
+    pub name: String,
+    pub params: HashMap<String, String>,
+    pub value: String,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct IcsEvent {
+    pub uid: Option<String>,
+    pub title: String,
+    /// Timestamp in ms
+    pub start: i64,
+    /// Timestamp in ms
+    pub end: i64,
+    /// Raw RRULE value
+    pub recurrence: Option<String>,
+    /// Excluded occurrences, timestamps in ms
+    pub exdates: Vec<i64>,
+    /// Time zone of DTSTART, in which the occurrences of recurring events are computed. None for UTC times.
+    pub timezone: Option<String>,
+}
+
+/// Parse every VEVENT of an ics file. Times without timezone information are interpreted in the given default timezone.
+/// Events that cannot be parsed are returned as errors so the caller can report them.
+pub fn parse_events(data: &str, default_timezone: &Tz) -> Vec<Result<IcsEvent, Error>> {
+    let mut events = vec![];
+    let mut current: Option<Vec<IcsProperty>> = None;
+    let mut depth = 0;
+    for property in unfold_lines(data).iter().filter_map(|line| parse_property(line)) {
+        match property.name.as_str() {
+            "BEGIN" => {
+                if property.value.eq_ignore_ascii_case("VEVENT") {
+                    current = Some(vec![]);
+                    depth = 0;
+                } else if current.is_some() {
+                    depth += 1;
+                }
+            }
+            "END" => {
+                if property.value.eq_ignore_ascii_case("VEVENT") {
+                    if let Some(properties) = current.take() {
+                        events.push(build_event(&properties, default_timezone));
+                    }
+                } else if current.is_some() {
+                    depth -= 1;
+                }
+            }
+            _ => {
+                // Ignore properties of nested components such as VALARM
+                if depth == 0 {
+                    if let Some(properties) = &mut current {
+                        properties.push(property);
+                    }
+                }
+            }
+        }
+    }
+    events
+}
+
+fn build_event(properties: &[IcsProperty], default_timezone: &Tz) -> Result<IcsEvent, Error> {
+    let mut event = IcsEvent::default();
+    let mut start = None;
+    let mut end = None;
+    let mut duration = None;
+    let mut is_date = false;
+    for property in properties {
+        match property.name.as_str() {
+            "UID" => event.uid = Some(property.value.clone()),
+            "SUMMARY" => event.title = unescape_text(&property.value),
+            "DTSTART" => {
+                is_date = is_date_value(property);
+                start = Some(parse_date_time(property, default_timezone)?);
+                event.timezone = match property.params.get("TZID") {
+                    Some(tzid) => Some(tzid.clone()),
+                    None if property.value.trim().ends_with('Z') || *default_timezone == Tz::UTC => None,
+                    None => Some(default_timezone.name().to_string()),
+                };
+            }
+            "DTEND" => end = Some(parse_date_time(property, default_timezone)?),
+            "DURATION" => duration = Some(parse_duration(&property.value)?),
+            "RRULE" => event.recurrence = Some(property.value.clone()),
+            "EXDATE" => {
+                for value in property.value.split(',') {
+                    event.exdates.push(parse_date_time(&IcsProperty {
+                        name: property.name.clone(),
+                        params: property.params.clone(),
+                        value: value.to_string(),
+                    }, default_timezone)?);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    event.start = start.ok_or(Error::msg("Missing DTSTART"))?;
+    event.end = match (end, duration) {
+        (Some(end), _) => end,
+        (None, Some(duration)) => event.start.checked_add(duration).ok_or(Error::msg("Event duration is out of range"))?,
+        // All day events without end last one day
+        (None, None) if is_date => event.start.checked_add(24 * 60 * 60 * 1000).ok_or(Error::msg("Event end is out of range"))?,
+        (None, None) => return Err(Error::msg("Missing DTEND")),
+    };
+    if event.end <= event.start {
+        return Err(Error::msg("Event ends before it starts"));
+    }
+    Ok(event)
+}
+
+/// Long lines are split on several lines starting with a whitespace (RFC 5545 3.1)
+fn unfold_lines(data: &str) -> Vec<String> {
+    let mut lines: Vec<String> = vec![];
+    for line in data.lines() {
+        let line = line.trim_end_matches('\r');
+        if line.starts_with(' ') || line.starts_with('\t') {
+            if let Some(last) = lines.last_mut() {
+                last.push_str(&line[1..]);
+                continue;
+            }
+        }
+        if !line.is_empty() {
+            lines.push(line.to_string());
+        }
+    }
+    lines
+}
+
+pub fn parse_property(line: &str) -> Option<IcsProperty> {
+    // The value starts after the first ':' that is not quoted inside a parameter
+    let mut in_quotes = false;
+    let mut separator = None;
+    for (index, char) in line.char_indices() {
+        match char {
+            '"' => in_quotes = !in_quotes,
+            ':' if !in_quotes => {
+                separator = Some(index);
+                break;
+            }
+            _ => {}
+        }
+    }
+    let separator = separator?;
+    let mut header = line[..separator].split(';');
+    let name = header.next()?.trim().to_uppercase();
+    let mut params = HashMap::new();
+    for param in header {
+        if let Some((key, value)) = param.split_once('=') {
+            params.insert(key.trim().to_uppercase(), value.trim_matches('"').to_string());
+        }
+    }
+    Some(IcsProperty {
+        name,
+        params,
+        value: line[separator + 1..].to_string(),
+    })
+}
+
+pub fn unescape_text(text: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(char) = chars.next() {
+        if char == '\\' {
+            match chars.next() {
+                Some('n') | Some('N') => result.push('\n'),
+                Some(other) => result.push(other),
+                None => {}
+            }
+        } else {
+            result.push(char);
+        }
+    }
+    result
+}
+
+fn is_date_value(property: &IcsProperty) -> bool {
+    property.params.get("VALUE").map(|value| value.eq_ignore_ascii_case("DATE")).unwrap_or(false) || property.value.len() == 8
+}
+
+/// Parse a DATE or DATE-TIME property into a timestamp in ms
+pub fn parse_date_time(property: &IcsProperty, default_timezone: &Tz) -> Result<i64, Error> {
+    let value = property.value.trim();
+    let timezone = match property.params.get("TZID") {
+        None => *default_timezone,
+        Some(tzid) => Tz::from_str(tzid).map_err(|_| Error::msg(format!("Unknown timezone {tzid}")))?,
+    };
+    let date_time = if is_date_value(property) {
+        NaiveDate::parse_from_str(value, "%Y%m%d")?.and_hms_opt(0, 0, 0).ok_or(Error::msg("Invalid date"))?
+    } else {
+        NaiveDateTime::parse_from_str(value.trim_end_matches('Z'), "%Y%m%dT%H%M%S")?
+    };
+    let date_time: DateTime<Utc> = if value.ends_with('Z') {
+        Utc.from_utc_datetime(&date_time)
+    } else {
+        timezone.from_local_datetime(&date_time).earliest().ok_or(Error::msg(format!("Invalid local time {value}")))?.with_timezone(&Utc)
+    };
+    Ok(date_time.timestamp_millis())
+}
+
+/// Parse a duration value like P1W, P1DT2H or PT30M into ms
+pub fn parse_duration(value: &str) -> Result<i64, Error> {
+    let value = value.trim();
+    let (sign, value) = match value.strip_prefix('-') {
+        Some(value) => (-1, value),
+        None => (1, value.strip_prefix('+').unwrap_or(value)),
+    };
+    let value = value.strip_prefix('P').ok_or(Error::msg(format!("Invalid duration {value}")))?;
+    let mut total: i64 = 0;
+    let mut number = String::new();
+    let mut in_time = false;
+    for char in value.chars() {
+        if char.is_ascii_digit() {
+            number.push(char);
+            continue;
+        }
+        let unit = match (char, in_time) {
+            ('T', _) => {
+                in_time = true;
+                continue;
+            }
+            ('W', false) => 7 * 24 * 60 * 60,
+            ('D', false) => 24 * 60 * 60,
+            ('H', true) => 60 * 60,
+            ('M', true) => 60,
+            ('S', true) => 1,
+            _ => return Err(Error::msg(format!("Invalid duration {value}"))),
+        };
+        total = i64::from_str(&number)?.checked_mul(unit)
+            .and_then(|amount| total.checked_add(amount))
+            .ok_or(Error::msg(format!("Duration {value} is out of range")))?;
+        number.clear();
+    }
+    total.checked_mul(sign * 1000).ok_or(Error::msg(format!("Duration {value} is out of range")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ics::writer::{escape_text, IcsWriter};
+
+    fn time(year: i32, month: u32, day: u32, hour: u32, minute: u32) -> i64 {
+        NaiveDate::from_ymd_opt(year, month, day).unwrap().and_hms_opt(hour, minute, 0).unwrap().and_utc().timestamp_millis()
+    }
+
+    fn parse_single(data: &str) -> Result<IcsEvent, Error> {
+        let mut events = parse_events(data, &Tz::UTC);
+        assert_eq!(events.len(), 1);
+        events.remove(0)
+    }
+
+    #[test]
+    fn durations() {
+        let cases = [
+            ("PT30M", 30 * 60 * 1000),
+            ("-PT15M", -15 * 60 * 1000),
+            ("+PT1S", 1000),
+            ("P1W", 7 * 24 * 60 * 60 * 1000),
+            ("P1DT2H", 26 * 60 * 60 * 1000),
+            ("PT1H30M15S", (90 * 60 + 15) * 1000),
+        ];
+        for (value, expected) in cases {
+            assert_eq!(parse_duration(value).unwrap(), expected, "{value}");
+        }
+        for value in ["1H", "PT1D", "P1H", "P1X", "P999999999999999W", "PT9223372036854775807S", "P9999999999999999999D"] {
+            assert!(parse_duration(value).is_err(), "{value}");
+        }
+    }
+
+    #[test]
+    fn events() {
+        let event = parse_single("BEGIN:VCALENDAR\r\nBEGIN:VEVENT\r\nUID:1\r\nSUMMARY:Lunch\\, with \\;friends\r\n \\nbring food\r\nDTSTART:20240101T120000Z\r\nDURATION:PT1H\r\nBEGIN:VALARM\r\nSUMMARY:Ignored\r\nEND:VALARM\r\nEND:VEVENT\r\nEND:VCALENDAR\r\n").unwrap();
+        assert_eq!(event.title, "Lunch, with ;friends\nbring food");
+        assert_eq!((event.start, event.end), (time(2024, 1, 1, 12, 0), time(2024, 1, 1, 13, 0)));
+
+        // All day events without end last one day
+        let event = parse_single("BEGIN:VEVENT\nDTSTART;VALUE=DATE:20240101\nEND:VEVENT").unwrap();
+        assert_eq!((event.start, event.end), (time(2024, 1, 1, 0, 0), time(2024, 1, 2, 0, 0)));
+
+        let event = parse_single("BEGIN:VEVENT\nDTSTART;TZID=Europe/Paris:20240101T120000\nDTEND;TZID=Europe/Paris:20240101T130000\nEXDATE;TZID=Europe/Paris:20240108T120000,20240115T120000\nRRULE:FREQ=WEEKLY\nEND:VEVENT").unwrap();
+        assert_eq!(event.start, time(2024, 1, 1, 11, 0));
+        assert_eq!(event.exdates, vec![time(2024, 1, 8, 11, 0), time(2024, 1, 15, 11, 0)]);
+        assert_eq!(event.recurrence.as_deref(), Some("FREQ=WEEKLY"));
+        assert_eq!(event.timezone.as_deref(), Some("Europe/Paris"));
+
+        for invalid in [
+            "BEGIN:VEVENT\nDTEND:20240101T120000Z\nEND:VEVENT",
+            "BEGIN:VEVENT\nDTSTART:20240101T120000Z\nEND:VEVENT",
+            "BEGIN:VEVENT\nDTSTART:20240101T120000Z\nDTEND:20240101T110000Z\nEND:VEVENT",
+            "BEGIN:VEVENT\nDTSTART:20240101T120000Z\nDURATION:-PT1H\nEND:VEVENT",
+            "BEGIN:VEVENT\nDTSTART:20240101T120000Z\nDURATION:P999999999999999W\nEND:VEVENT",
+            "BEGIN:VEVENT\nDTSTART;TZID=Nowhere/Unknown:20240101T120000\nDURATION:PT1H\nEND:VEVENT",
+        ] {
+            assert!(parse_single(invalid).is_err(), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn parse_written_events() {
+        let title = "A long title, with; special characters\nand a second line, long enough to be folded by the writer é";
+        let mut writer = IcsWriter::new("Calendar");
+        writer.add_event("1@schedulator", time(2024, 3, 10, 8, 30), time(2024, 3, 10, 9, 45), &Tz::UTC, title, &[
+            ("X-SCHEDULATOR-PARTICIPANT", escape_text("Pierre, Even")),
+            ("RRULE", String::from("FREQ=WEEKLY;COUNT=3")),
+        ]);
+        writer.add_event("2@schedulator", time(2024, 3, 11, 0, 0), time(2024, 3, 12, 0, 0), &Tz::Europe__Paris, "Second", &[]);
+        let data = writer.finish();
+        assert!(data.lines().all(|line| line.len() <= 75));
+
+        let events: Vec<IcsEvent> = parse_events(&data, &Tz::UTC).into_iter().map(Result::unwrap).collect();
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0].uid.as_deref(), Some("1@schedulator"));
+        assert_eq!(events[0].title, title);
+        assert_eq!((events[0].start, events[0].end), (time(2024, 3, 10, 8, 30), time(2024, 3, 10, 9, 45)));
+        assert_eq!(events[0].recurrence.as_deref(), Some("FREQ=WEEKLY;COUNT=3"));
+        assert_eq!(events[0].timezone, None);
+        assert_eq!(events[1].title, "Second");
+        assert_eq!((events[1].start, events[1].end), (time(2024, 3, 11, 0, 0), time(2024, 3, 12, 0, 0)));
+        assert_eq!(events[1].timezone.as_deref(), Some("Europe/Paris"));
+    }
+}
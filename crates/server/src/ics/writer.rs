@@ -0,0 +1,75 @@
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
+
+/// Build an ics file event by event
+pub struct IcsWriter {
+    data: String,
+}
+
+impl IcsWriter {
+    pub fn new(name: &str) -> Self {
+        let mut writer = Self { data: String::new() };
+        writer.line("BEGIN:VCALENDAR");
+        writer.line("VERSION:2.0");
+        writer.line("PRODID:-//schedulator//schedulator//EN");
+        writer.line("CALSCALE:GREGORIAN");
+        writer.line(&format!("X-WR-CALNAME:{}", escape_text(name)));
+        writer
+    }
+
+    /// Add an event. Start and end are timestamps in ms, written in local time when a time zone other than UTC is given
+    /// so that recurrence rules are expanded in this time zone. Extra properties are written as is.
+    pub fn add_event(&mut self, uid: &str, start: i64, end: i64, timezone: &Tz, summary: &str, properties: &[(&str, String)]) {
+        self.line("BEGIN:VEVENT");
+        self.line(&format!("UID:{uid}"));
+        self.line(&format!("DTSTAMP:{}", format_date_time(Utc::now().timestamp_millis())));
+        if *timezone == Tz::UTC {
+            self.line(&format!("DTSTART:{}", format_date_time(start)));
+            self.line(&format!("DTEND:{}", format_date_time(end)));
+        } else {
+            self.line(&format!("DTSTART;TZID={}:{}", timezone.name(), format_local_date_time(start, timezone)));
+            self.line(&format!("DTEND;TZID={}:{}", timezone.name(), format_local_date_time(end, timezone)));
+        }
+        self.line(&format!("SUMMARY:{}", escape_text(summary)));
+        for (name, value) in properties {
+            self.line(&format!("{name}:{value}"));
+        }
+        self.line("END:VEVENT");
+    }
+
+    pub fn finish(mut self) -> String {
+        self.line("END:VCALENDAR");
+        self.data
+    }
+
+    /// Lines longer than 75 octets are folded (RFC 5545 3.1)
+    fn line(&mut self, line: &str) {
+        let mut length = 0;
+        for char in line.chars() {
+            if length + char.len_utf8() > 75 {
+                self.data.push_str("\r\n ");
+                length = 1;
+            }
+            self.data.push(char);
+            length += char.len_utf8();
+        }
+        self.data.push_str("\r\n");
+    }
+}
+
+pub fn escape_text(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace(';', "\\;")
+        .replace(',', "\\,")
+        .replace('\n', "\\n")
+}
+
+/// Format a timestamp in ms as an UTC DATE-TIME value
+pub fn format_date_time(time: i64) -> String {
+    DateTime::<Utc>::from_timestamp_millis(time).unwrap_or_default().format("%Y%m%dT%H%M%SZ").to_string()
+}
+
+/// Format a timestamp in ms as a local DATE-TIME value, to be used with a TZID parameter
+pub fn format_local_date_time(time: i64, timezone: &Tz) -> String {
+    DateTime::<Utc>::from_timestamp_millis(time).unwrap_or_default().with_timezone(timezone).format("%Y%m%dT%H%M%S").to_string()
+}
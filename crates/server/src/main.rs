@@ -1,7 +1,14 @@
 use crate::config::{Config, WebClientConfig};
+use crate::database::auth_token::AuthToken;
+use crate::database::calendar_share_links::CalendarShareLink;
+use crate::database::calendar_users::CalendarUser;
+use crate::database::email_changes::EmailChange;
+use crate::database::email_verifications::EmailVerification;
+use crate::database::migrations::MigrationState;
+use crate::database::reset_passwords::ResetPasswords;
 use crate::database::user::User;
 use crate::routes::app_ctx::AppCtx;
-use crate::routes::{ApiRoutes, RequestContext};
+use crate::routes::{client_ip, ApiRoutes, RequestContext};
 use crate::server_error::ServerError;
 use crate::types::enc_string::EncString;
 use crate::web_client::{get_origin, WebClient};
@@ -23,6 +30,7 @@ use std::path::PathBuf;
 use std::str::FromStr;
 use std::sync::atomic::Ordering::SeqCst;
 use std::sync::Arc;
+use std::time::Duration;
 use std::{env, fs};
 use tracing::{error, info, warn, Level};
 use tracing_subscriber::layer::SubscriberExt;
@@ -30,7 +38,10 @@ use tracing_subscriber::{filter, fmt, Layer, Registry};
 
 mod config;
 mod database;
+mod emailer;
+mod ics;
 mod routes;
+mod scheduling;
 mod server_error;
 mod types;
 mod web_client;
@@ -94,7 +105,7 @@ impl Server {
                 if let Some(tls_config) = &tls_config {
                     match axum_server_dual_protocol::bind_dual_protocol(addr, tls_config.clone())
                         .set_upgrade(true)
-                        .serve(router.into_make_service())
+                        .serve(router.into_make_service_with_connect_info::<SocketAddr>())
                         .await
                     {
                         Ok(_) => {}
@@ -111,7 +122,7 @@ impl Server {
                                 return;
                             }
                         },
-                        router,
+                        router.into_make_service_with_connect_info::<SocketAddr>(),
                     )
                         .await
                         .unwrap();
@@ -225,19 +236,45 @@ async fn main() {
     /*********************** READ ARGS  ***********************/
 
     let args: Vec<String> = env::args().collect();
-    let mut it = args.iter();
+    let mut it = args.iter().peekable();
     it.next().expect("Expected first arg");
+    let default_migrations = PathBuf::from(&config.backend_config.postgres.default_migrations);
     while let Some(arg) = it.next() {
         match arg.as_str() {
+            // -migrate [status|--dry-run|rollback <n>] [<migration_dir>]
             "-migrate" => {
-                let dir = it.next().expect("Missing <migration_dir> parameter");
-                ctx.database
-                    .migrate(
-                        PathBuf::from(dir),
-                        &config.backend_config.postgres.scheme_name,
-                    )
-                    .await
-                    .expect("Failed to migrate database");
+                let mode = it.next_if(|arg| *arg == "status" || *arg == "--dry-run" || *arg == "rollback").cloned();
+                let rollback_count = match mode.as_deref() {
+                    Some("rollback") => usize::from_str(it.next().expect("Missing <n> parameter")).expect("Invalid <n> parameter"),
+                    _ => 0,
+                };
+                let dir = it.next().map(PathBuf::from).unwrap_or(default_migrations.clone());
+                match mode.as_deref() {
+                    Some("status") => {
+                        let format_timestamp = |date: i64| DateTime::<Utc>::from_timestamp_millis(date).unwrap_or_default().to_rfc3339();
+                        for migration in ctx.database.migration_status(&dir).await.expect("Failed to read migration status") {
+                            match migration.state {
+                                MigrationState::Applied(date) => info!("[applied {}] {}", format_timestamp(date), migration.version),
+                                MigrationState::Pending => info!("[pending] {}", migration.version),
+                                MigrationState::Changed(date) => warn!("[changed since {}] {}", format_timestamp(date), migration.version),
+                                MigrationState::Missing(date) => warn!("[missing file, applied {}] {}", format_timestamp(date), migration.version),
+                            }
+                        }
+                    }
+                    Some("rollback") => {
+                        let reverted = ctx.database.rollback(&dir, rollback_count).await.expect("Failed to rollback migrations");
+                        info!("Reverted {} migration(s)", reverted.len());
+                    }
+                    mode => {
+                        let dry_run = mode == Some("--dry-run");
+                        let applied = ctx.database.migrate(&dir, dry_run).await.expect("Failed to migrate database");
+                        if applied.is_empty() {
+                            info!("Database is up to date");
+                        } else if dry_run {
+                            info!("{} migration(s) would be applied", applied.len());
+                        }
+                    }
+                }
                 return;
             }
             val => {
@@ -247,6 +284,49 @@ async fn main() {
         }
     }
 
+    if let Err(error) = ctx.database.migrate(&default_migrations, false).await {
+        error!("Failed to migrate database : {error}");
+        return;
+    }
+
+    match CalendarUser::anonymous_in_restricted_calendars(&ctx.database).await {
+        Ok(users) => {
+            for user in users {
+                warn!("Anonymous user {} ({}) is part of calendar {} which requires an account. It can be assigned to an account or removed by the calendar editors.", user.id(), user.name, user.calendar_id);
+            }
+        }
+        Err(error) => error!("Failed to list anonymous calendar users : {error}"),
+    }
+
+    // Periodically remove expired sessions and reset codes
+    let purge_ctx = ctx.clone();
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(purge_ctx.config.backend_config.sessions.purge_interval.max(1)));
+        loop {
+            interval.tick().await;
+            match AuthToken::purge_expired(&purge_ctx.database).await {
+                Ok(count) if count > 0 => info!("Removed {count} expired authentication tokens"),
+                Ok(_) => {}
+                Err(err) => error!("Failed to remove expired authentication tokens : {err}"),
+            }
+            match ResetPasswords::purge_expired(&purge_ctx.database).await {
+                Ok(count) if count > 0 => info!("Removed {count} expired password reset codes"),
+                Ok(_) => {}
+                Err(err) => error!("Failed to remove expired password reset codes : {err}"),
+            }
+            match EmailChange::purge_expired(&purge_ctx.database).await {
+                Ok(count) if count > 0 => info!("Removed {count} expired email change requests"),
+                Ok(_) => {}
+                Err(err) => error!("Failed to remove expired email change requests : {err}"),
+            }
+            match EmailVerification::purge_expired(&purge_ctx.database).await {
+                Ok(count) if count > 0 => info!("Removed {count} expired email verification codes"),
+                Ok(_) => {}
+                Err(err) => error!("Failed to remove expired email verification codes : {err}"),
+            }
+        }
+    });
+
     // Start web client
     start_web_client(config.web_client_config.clone()).await;
 
@@ -309,11 +389,25 @@ pub async fn middleware_get_request_context(
     };
 
     if let Some(token) = token {
-        context.connected_user =
-            tokio::sync::RwLock::new(match User::from_auth_token(&ctx.database, &token?).await {
-                Ok(connected_user) => Some(connected_user),
-                Err(_) => None,
-            })
+        // Expired or unknown tokens are ignored : the request is handled as anonymous
+        if let Ok((connected_user, token)) = User::from_auth_token(&ctx.database, &token?, client_ip(&ctx, &request)).await {
+            context.connected_user = tokio::sync::RwLock::new(Some(connected_user));
+            context.session = Some(token.id().clone());
+        }
+    }
+
+    let share_token = match jar.get("sharetoken") {
+        None => request
+            .headers()
+            .get("content-share-token")
+            .map(EncString::try_from),
+        Some(token) => Some(EncString::from_url_path(token.value().to_string())),
+    };
+    if let Some(share_token) = share_token {
+        // Like auth tokens, invalid share links are ignored
+        if let Ok(link) = CalendarShareLink::from_bearer(&ctx.database, &share_token?).await {
+            context.share_link = Some(link);
+        }
     }
 
     let uri = request.uri().clone();
@@ -2,10 +2,14 @@
 use anyhow::Error;
 use crate::config::Config;
 use crate::database::Database;
+use crate::routes::live_updates::LiveUpdates;
+use crate::routes::rate_limit::RateLimiter;
 
 pub struct AppCtx {
     pub config: Config,
-    pub database: Database
+    pub database: Database,
+    pub live: LiveUpdates,
+    pub rate_limiter: RateLimiter,
 }
 
 impl AppCtx {
@@ -15,6 +19,8 @@ impl AppCtx {
         Ok(Self {
             config,
             database,
+            live: LiveUpdates::default(),
+            rate_limiter: RateLimiter::default(),
         })
     }
 }
\ No newline at end of file
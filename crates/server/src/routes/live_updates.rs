@@ -0,0 +1,139 @@
+use crate::database::calendar::Calendar;
+use crate::database::calendar_users::CalendarUser;
+use crate::database::event::Event;
+use crate::types::database_ids::{CalendarId, CalendarUserId, EventId};
+use rand::distr::{Alphanumeric, SampleString};
+use serde::Serialize;
+use std::collections::{HashMap, VecDeque};
+use std::str::FromStr;
+use std::sync::Mutex;
+use tokio::sync::broadcast;
+use tracing::error;
+
+/// Number of messages kept per calendar to let clients resume after a reconnection
+const HISTORY_SIZE: usize = 256;
+
+#[derive(Serialize, Debug, Clone)]
+#[serde(tag = "type", content = "data", rename_all = "snake_case")]
+pub enum LiveEvent {
+    EventCreated(Event),
+    EventUpdated(Event),
+    EventDeleted(EventId),
+    UserAdded(CalendarUser),
+    UserUpdated(CalendarUser),
+    UserRemoved(CalendarUserId),
+    CalendarUpdated(Calendar),
+    CalendarDeleted(CalendarId),
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct LiveMessage {
+    pub seq: u64,
+    #[serde(flatten)]
+    pub event: LiveEvent,
+}
+
+struct CalendarChannel {
+    next_seq: u64,
+    history: VecDeque<LiveMessage>,
+    sender: broadcast::Sender<LiveMessage>,
+}
+
+impl CalendarChannel {
+    fn new() -> Self {
+        Self {
+            next_seq: 1,
+            history: VecDeque::with_capacity(HISTORY_SIZE),
+            sender: broadcast::channel(HISTORY_SIZE).0,
+        }
+    }
+}
+
+/// Result of a subscription to the live updates of a calendar
+pub struct LiveSubscription {
+    /// Messages missed since the requested event id
+    pub missed: Vec<LiveMessage>,
+    /// The requested event id is too old, unknown or was sent before a restart : the client should reload the whole calendar
+    pub reset: bool,
+    /// Sequence number the client resumes from, 0 if it starts over
+    pub since: u64,
+    pub receiver: broadcast::Receiver<LiveMessage>,
+}
+
+/// Dispatch the modifications made to calendars to the connected clients
+pub struct LiveUpdates {
+    /// Random id of this process. Sequence numbers restart with the process, so the event ids sent to the clients carry it.
+    epoch: String,
+    channels: Mutex<HashMap<CalendarId, CalendarChannel>>,
+}
+
+impl Default for LiveUpdates {
+    fn default() -> Self {
+        Self {
+            epoch: Alphanumeric.sample_string(&mut rand::rng(), 8),
+            channels: Mutex::default(),
+        }
+    }
+}
+
+impl LiveUpdates {
+    /// Id of a message in the event stream : the epoch of the process followed by the sequence number of the message
+    pub fn event_id(&self, message: &LiveMessage) -> String {
+        format!("{}-{}", self.epoch, message.seq)
+    }
+
+    pub fn publish(&self, calendar: &CalendarId, event: LiveEvent) {
+        let mut channels = match self.channels.lock() {
+            Ok(channels) => channels,
+            Err(err) => {
+                error!("Live updates lock is poisoned : {err}");
+                return;
+            }
+        };
+        let channel = channels.entry(calendar.clone()).or_insert_with(CalendarChannel::new);
+        let message = LiveMessage { seq: channel.next_seq, event };
+        channel.next_seq += 1;
+        if channel.history.len() >= HISTORY_SIZE {
+            channel.history.pop_front();
+        }
+        channel.history.push_back(message.clone());
+        // Sending only fails when nobody is listening
+        let _ = channel.sender.send(message);
+    }
+
+    /// Subscribe to the next messages of a calendar. If the id of the last received event is provided, the messages that
+    /// were published after it are returned too.
+    pub fn subscribe(&self, calendar: &CalendarId, since: Option<&str>) -> Option<LiveSubscription> {
+        let since = since.map(|since| since.split_once('-')
+            .filter(|(epoch, _)| *epoch == self.epoch)
+            .and_then(|(_, seq)| u64::from_str(seq).ok()));
+        let mut channels = self.channels.lock().ok()?;
+        let channel = channels.entry(calendar.clone()).or_insert_with(CalendarChannel::new);
+        let receiver = channel.sender.subscribe();
+        let (missed, reset) = match since {
+            None => (vec![], false),
+            // The id is invalid or was sent before the server restarted
+            Some(None) => (vec![], true),
+            Some(Some(since)) => {
+                let oldest = channel.history.front().map(|message| message.seq).unwrap_or(channel.next_seq);
+                // Either messages were dropped from the history, or the channel was closed since the last connection
+                if since + 1 < oldest || since >= channel.next_seq {
+                    (vec![], true)
+                } else {
+                    (channel.history.iter().filter(|message| message.seq > since).cloned().collect(), false)
+                }
+            }
+        };
+        let since = match since {
+            Some(Some(since)) if !reset => since,
+            _ => 0,
+        };
+        Some(LiveSubscription { missed, reset, since, receiver })
+    }
+
+    pub fn close(&self, calendar: &CalendarId) {
+        if let Ok(mut channels) = self.channels.lock() {
+            channels.remove(calendar);
+        }
+    }
+}
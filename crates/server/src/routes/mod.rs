@@ -1,20 +1,29 @@
+use std::net::SocketAddr;
 use std::sync::Arc;
 use std::sync::atomic::AtomicBool;
 use anyhow::Error;
 use axum::body::Body;
 use axum::http::{Request, StatusCode};
+use axum::extract::{ConnectInfo, State};
 use axum::response::{IntoResponse};
-use axum::Router;
+use axum::routing::get;
+use axum::{Json, Router};
 use tracing::warn;
 use crate::database::calendar::Calendar;
+use crate::database::calendar_share_links::CalendarShareLink;
 use crate::database::user::User;
 use crate::routes::app_ctx::AppCtx;
 use crate::routes::route_calendar::CalendarRoutes;
 use crate::routes::route_event::EventRoutes;
 use crate::routes::route_user::UserRoutes;
+use crate::server_error::ServerError;
+use crate::types::database_ids::AuthTokenId;
 
 mod route_calendar;
 pub mod app_ctx;
+pub mod live_updates;
+pub mod permissions;
+pub mod rate_limit;
 pub mod route_event;
 pub mod route_user;
 
@@ -49,6 +58,18 @@ macro_rules! require_connected_user {
 }
 
 
+/// Get the connected user and the calendar share link used by the request
+#[macro_export]
+macro_rules! get_visitor {
+    ($request:expr) => {{
+        let req_ctx = $request.extensions().get::<std::sync::Arc<$crate::routes::RequestContext>>().unwrap();
+        $crate::routes::permissions::Visitor {
+            user: req_ctx.connected_user().await.clone(),
+            share_link: req_ctx.share_link.clone(),
+        }
+    }};
+}
+
 #[macro_export]
 macro_rules! get_display_calendar {
     ($request:expr, $prop:ident, $body:expr, $or_else:expr) => {{
@@ -86,9 +107,25 @@ macro_rules! get_action {
     );
 }
 
+/// Address of the client. The X-Forwarded-For header is used when the server runs behind a trusted reverse proxy.
+pub fn client_ip(ctx: &AppCtx, request: &Request<Body>) -> Option<String> {
+    if ctx.config.trust_proxy_headers {
+        if let Some(forwarded) = request.headers().get("x-forwarded-for").and_then(|header| header.to_str().ok()) {
+            if let Some(ip) = forwarded.split(',').next() {
+                return Some(ip.trim().to_string());
+            }
+        }
+    }
+    request.extensions().get::<ConnectInfo<SocketAddr>>().map(|info| info.0.ip().to_string())
+}
+
 #[derive(Default, Debug)]
 pub struct RequestContext {
     pub connected_user: tokio::sync::RwLock<Option<User>>,
+    /// Authentication token used by the connected user
+    pub session: Option<AuthTokenId>,
+    /// Calendar share link used by the visitor
+    pub share_link: Option<CalendarShareLink>,
     pub display_calendar: tokio::sync::RwLock<Option<Calendar>>,
     pub is_web_client: AtomicBool,
 }
@@ -120,11 +157,19 @@ impl ApiRoutes {
             .nest("/calendar", CalendarRoutes::create(ctx)?)
             .nest("/event", EventRoutes::create(ctx)?)
             .nest("/user", UserRoutes::create(ctx)?)
+            .route("/health", get(health).with_state(ctx.clone()))
             .fallback(handler_404);
         Ok(router)
     }
 }
 
+async fn health(State(ctx): State<Arc<AppCtx>>) -> Result<impl IntoResponse, ServerError> {
+    match ctx.database.health_check().await {
+        Ok(status) => Ok(Json(status)),
+        Err(err) => Err(ServerError::msg(StatusCode::SERVICE_UNAVAILABLE, format!("Database is unreachable : {err}"))),
+    }
+}
+
 async fn handler_404(_: Request<Body>) -> impl IntoResponse {
     warn!("\t\t'-> 404 : NOT FOUND");
     (StatusCode::NOT_FOUND, "Not found !")
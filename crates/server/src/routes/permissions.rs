@@ -0,0 +1,113 @@
+use crate::database::calendar::Calendar;
+use crate::database::calendar_members::{CalendarMember, CalendarRole};
+use crate::database::calendar_share_links::CalendarShareLink;
+use crate::database::calendar_users::CalendarUser;
+use crate::database::user::User;
+use crate::database::Database;
+use crate::server_error::ServerError;
+use crate::types::enc_string::EncString;
+use axum::http::StatusCode;
+
+/// Who is sending a request : the connected user and the share link used, if any
+#[derive(Default, Clone, Debug)]
+pub struct Visitor {
+    pub user: Option<User>,
+    pub share_link: Option<CalendarShareLink>,
+}
+
+impl From<User> for Visitor {
+    fn from(user: User) -> Self {
+        Self { user: Some(user), share_link: None }
+    }
+}
+
+/// Find a calendar from the key given in a request : its primary key, or the token of the share link used by the visitor.
+/// Visitors only know the key of the link they opened, so it is used in place of the primary key of the calendar.
+pub async fn find_calendar(db: &Database, key: &EncString, visitor: &Visitor) -> Result<Calendar, ServerError> {
+    let calendar = match &visitor.share_link {
+        Some(link) if link.token.encoded() == key.encoded() => Calendar::from_id(db, &link.calendar_id).await,
+        _ => Calendar::from_key(db, key).await,
+    };
+    calendar.map_err(|err| ServerError::msg(StatusCode::NOT_FOUND, err))
+}
+
+/// Get the role of a visitor over a calendar. Visitors coming through a share link get the role of the link, or their own
+/// if they are members with a higher role. Other visitors that are not members get the default role of the calendar.
+/// Anonymous visitors of a calendar requiring an account can only view it.
+pub async fn calendar_role(db: &Database, calendar: &Calendar, visitor: &Visitor) -> Result<CalendarRole, ServerError> {
+    let member_role = match &visitor.user {
+        None => None,
+        Some(user) if calendar.owner_id == *user.id() => Some(CalendarRole::Owner),
+        Some(user) => CalendarMember::from_user(db, calendar.id(), user.id()).await?.map(|member| member.role),
+    };
+    let role = match (&visitor.share_link, member_role) {
+        (Some(link), Some(role)) if link.calendar_id == *calendar.id() => role.max(link.scope),
+        (Some(link), None) if link.calendar_id == *calendar.id() => link.scope,
+        (_, Some(role)) => role,
+        (_, None) => calendar.default_role,
+    };
+    Ok(match &visitor.user {
+        None if calendar.require_account => role.min(CalendarRole::Viewer),
+        _ => role,
+    })
+}
+
+/// Ensure the given user has at least the required role on a calendar. Returns the role of the user.
+pub async fn check_calendar_permission(
+    db: &Database,
+    calendar: &Calendar,
+    visitor: &Visitor,
+    required: CalendarRole,
+) -> Result<CalendarRole, ServerError> {
+    let role = calendar_role(db, calendar, visitor).await?;
+    if role >= required {
+        Ok(role)
+    } else if visitor.user.is_none() && calendar.require_account {
+        Err(ServerError::msg(
+            StatusCode::UNAUTHORIZED,
+            "This calendar requires an account",
+        ))
+    } else {
+        Err(ServerError::msg(
+            StatusCode::FORBIDDEN,
+            format!("Forbidden : the {required} role is required on this calendar"),
+        ))
+    }
+}
+
+/// Ensure the given user is allowed to create or remove events on behalf of a calendar user
+pub async fn check_calendar_user_write(
+    db: &Database,
+    calendar: &Calendar,
+    calendar_user: &CalendarUser,
+    visitor: &Visitor,
+) -> Result<(), ServerError> {
+    if calendar_user.calendar_id != *calendar.id() {
+        return Err(ServerError::msg(
+            StatusCode::FORBIDDEN,
+            "Forbidden : this user is not part of this calendar",
+        ));
+    }
+    let role = check_calendar_permission(db, calendar, visitor, CalendarRole::Participant).await?;
+    if calendar_user.user_id.is_none() && calendar.require_account {
+        return Err(ServerError::msg(
+            StatusCode::FORBIDDEN,
+            "Forbidden : anonymous users are not allowed in this calendar",
+        ));
+    }
+    if role >= CalendarRole::Editor {
+        return Ok(());
+    }
+
+    match &calendar_user.user_id {
+        // Anonymous participants can be edited by any participant
+        None => Ok(()),
+        Some(owner) => match &visitor.user {
+            Some(user) if *user.id() == *owner => Ok(()),
+            _ => Err(ServerError::msg(
+                StatusCode::FORBIDDEN,
+                "Forbidden : not controlling this calendar user",
+            )),
+        },
+    }
+}
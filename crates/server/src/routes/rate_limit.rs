@@ -0,0 +1,161 @@
+use crate::config::RateLimitConfig;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Entries are only cleaned up once the map grows over this size
+const PRUNE_THRESHOLD: usize = 4096;
+
+#[derive(Default)]
+struct Entry {
+    window_start: Option<Instant>,
+    requests: u32,
+    failures: u32,
+    last_failure: Option<Instant>,
+    locked_until: Option<Instant>,
+}
+
+/// Count the requests and failed attempts per key (an ip, an account...), and lock the keys that are abused
+#[derive(Default)]
+pub struct RateLimiter {
+    entries: Mutex<HashMap<String, Entry>>,
+}
+
+impl RateLimiter {
+    /// Register a request. Fails with the delay to wait if the key is locked or made too many requests in the current window.
+    pub fn hit(&self, key: &str, limit: u32, config: &RateLimitConfig) -> Result<(), Duration> {
+        let now = Instant::now();
+        let window = Duration::from_secs(config.window);
+        let Ok(mut entries) = self.entries.lock() else { return Ok(()) };
+        if entries.len() > PRUNE_THRESHOLD {
+            let forget_after = Duration::from_secs(config.max_lockout_duration);
+            entries.retain(|_, entry| {
+                entry.locked_until.is_some_and(|locked_until| locked_until > now)
+                    || entry.window_start.is_some_and(|start| now - start < window)
+                    || entry.last_failure.is_some_and(|last_failure| now - last_failure < forget_after)
+            });
+        }
+
+        let entry = entries.entry(key.to_string()).or_default();
+        if let Some(locked_until) = entry.locked_until {
+            if locked_until > now {
+                return Err(locked_until - now);
+            }
+        }
+        match entry.window_start {
+            Some(start) if now - start < window => {
+                if entry.requests >= limit {
+                    return Err(window - (now - start));
+                }
+                entry.requests += 1;
+            }
+            _ => {
+                entry.window_start = Some(now);
+                entry.requests = 1;
+            }
+        }
+        Ok(())
+    }
+
+    /// Fails with the delay to wait if the key is locked, without counting a request
+    pub fn check_lock(&self, key: &str) -> Result<(), Duration> {
+        let now = Instant::now();
+        let Ok(entries) = self.entries.lock() else { return Ok(()) };
+        match entries.get(key).and_then(|entry| entry.locked_until) {
+            Some(locked_until) if locked_until > now => Err(locked_until - now),
+            _ => Ok(()),
+        }
+    }
+
+    /// Register a failed attempt. Once the threshold is reached, each new failure locks the key for twice as long as the previous one.
+    pub fn failure(&self, key: &str, config: &RateLimitConfig) {
+        let now = Instant::now();
+        let max_lockout = Duration::from_secs(config.max_lockout_duration);
+        let Ok(mut entries) = self.entries.lock() else { return };
+        let entry = entries.entry(key.to_string()).or_default();
+        if entry.last_failure.is_some_and(|last_failure| now - last_failure > max_lockout) {
+            entry.failures = 0;
+        }
+        entry.failures += 1;
+        entry.last_failure = Some(now);
+        if entry.failures >= config.lockout_threshold {
+            let exponent = (entry.failures - config.lockout_threshold).min(31);
+            let lockout = Duration::from_secs(config.lockout_duration).saturating_mul(1 << exponent).min(max_lockout);
+            entry.locked_until = Some(now + lockout);
+        }
+    }
+
+    /// Forget the failed attempts of a key
+    pub fn success(&self, key: &str) {
+        if let Ok(mut entries) = self.entries.lock() {
+            if let Some(entry) = entries.get_mut(key) {
+                entry.failures = 0;
+                entry.last_failure = None;
+                entry.locked_until = None;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> RateLimitConfig {
+        RateLimitConfig { lockout_threshold: 3, lockout_duration: 30, max_lockout_duration: 100, ..Default::default() }
+    }
+
+    /// Remaining lockout of a key in whole seconds, rounded up
+    fn lockout(limiter: &RateLimiter, key: &str) -> Option<u64> {
+        limiter.check_lock(key).err().map(|delay| delay.as_secs_f64().ceil() as u64)
+    }
+
+    #[test]
+    fn lockout_after_threshold() {
+        let (limiter, config) = (RateLimiter::default(), config());
+        limiter.failure("key", &config);
+        limiter.failure("key", &config);
+        assert_eq!(lockout(&limiter, "key"), None);
+        assert!(limiter.hit("key", u32::MAX, &config).is_ok());
+        limiter.failure("key", &config);
+        assert_eq!(lockout(&limiter, "key"), Some(30));
+        assert!(limiter.hit("key", u32::MAX, &config).is_err());
+        // Other keys are not affected
+        assert!(limiter.hit("other", u32::MAX, &config).is_ok());
+    }
+
+    #[test]
+    fn lockout_doubles_up_to_max() {
+        let (limiter, config) = (RateLimiter::default(), config());
+        let mut lockouts = vec![];
+        for _ in 0..5 {
+            limiter.failure("key", &config);
+            lockouts.push(lockout(&limiter, "key"));
+        }
+        assert_eq!(lockouts, vec![None, None, Some(30), Some(60), Some(100)]);
+    }
+
+    #[test]
+    fn success_resets_failures() {
+        let (limiter, config) = (RateLimiter::default(), config());
+        for _ in 0..4 {
+            limiter.failure("key", &config);
+        }
+        limiter.success("key");
+        assert_eq!(lockout(&limiter, "key"), None);
+        assert!(limiter.hit("key", u32::MAX, &config).is_ok());
+        // The count starts over
+        limiter.failure("key", &config);
+        limiter.failure("key", &config);
+        assert_eq!(lockout(&limiter, "key"), None);
+    }
+
+    #[test]
+    fn requests_per_window() {
+        let (limiter, config) = (RateLimiter::default(), config());
+        for _ in 0..3 {
+            assert!(limiter.hit("key", 3, &config).is_ok());
+        }
+        assert!(limiter.hit("key", 3, &config).is_err());
+    }
+}
@@ -1,19 +1,38 @@
 use crate::database::calendar::Calendar;
-use crate::database::calendar_users::CalendarUser;
+use crate::database::calendar_feed::CalendarFeed;
+use crate::database::calendar_members::{CalendarMember, CalendarRole};
+use crate::database::calendar_share_links::CalendarShareLink;
+use crate::database::calendar_user_claims::CalendarUserClaim;
+use crate::database::calendar_users::{CalendarUser, KeepOnConflict, MergeResult, NewCalendarUser};
+use crate::database::event::Event;
+use crate::database::user::User;
+use crate::ics::writer::{escape_text, format_date_time, IcsWriter};
 use crate::routes::app_ctx::AppCtx;
+use crate::routes::client_ip;
+use crate::routes::live_updates::{LiveEvent, LiveMessage};
+use crate::routes::permissions::{check_calendar_permission, find_calendar, Visitor};
+use crate::scheduling::availability::{calendar_slots, compute_availability, fit_in_slots, ONE_DAY_MS};
+use crate::scheduling::free_days::{free_days, DayStatus, FreeDaysOptions};
+use crate::scheduling::recurrence::{expand_events, series_timezone, Recurrence};
+use crate::scheduling::suggest::{suggest_windows, SuggestOptions};
 use crate::server_error::ServerError;
-use crate::types::database_ids::{CalendarId, CalendarUserId};
+use crate::types::database_ids::{CalendarId, CalendarUserId, DatabaseId, ShareLinkId, UserId};
 use crate::types::enc_string::EncString;
-use crate::{get_connected_user, require_connected_user};
+use crate::{get_visitor, require_connected_user};
 use anyhow::Error;
 use axum::body::Body;
-use axum::extract::{FromRequest, Path, Request, State};
-use axum::http::StatusCode;
+use axum::extract::{FromRequest, Path, Query, Request, State};
+use axum::http::{header, StatusCode};
+use axum::response::sse::{Event as SseEvent, KeepAlive, Sse};
 use axum::response::IntoResponse;
-use axum::routing::{get, post};
+use axum::routing::{get, patch, post};
 use axum::{Json, Router};
 use serde::{Deserialize, Serialize};
+use std::str::FromStr;
 use std::sync::Arc;
+use tokio_stream::wrappers::errors::BroadcastStreamRecvError;
+use tokio_stream::wrappers::BroadcastStream;
+use tokio_stream::StreamExt;
 
 pub struct CalendarRoutes {}
 
@@ -24,9 +43,30 @@ impl CalendarRoutes {
             .route("/delete", post(delete).with_state(ctx.clone()))
             .route("/my_calendars", get(my_calendars).with_state(ctx.clone()))
             .route("/get/{key}", get(get_calendar).with_state(ctx.clone()))
+            .route("/{key}", patch(update).with_state(ctx.clone()))
+            .route("/{key}/availability", get(availability).with_state(ctx.clone()))
+            .route("/{key}/suggest", get(suggest).with_state(ctx.clone()))
+            .route("/{key}/free-days", get(free_days_list).with_state(ctx.clone()))
+            .route("/{key}/feed-token", get(feed_token).post(rotate_feed_token).with_state(ctx.clone()))
+            .route("/{key}/feed.ics", get(feed).with_state(ctx.clone()))
+            .route("/{key}/live", get(live).with_state(ctx.clone()))
+            .route("/{key}/members", get(members).post(set_member).with_state(ctx.clone()))
+            .route("/{key}/members/remove", post(remove_member).with_state(ctx.clone()))
+            .route("/{key}/share-links", get(share_links).post(create_share_link).with_state(ctx.clone()))
+            .route("/{key}/share-links/revoke", post(revoke_share_link).with_state(ctx.clone()))
+            .route("/{key}/rotate-key", post(rotate_key).with_state(ctx.clone()))
+            .route("/{key}/anonymous-users", get(anonymous_users).with_state(ctx.clone()))
+            .route("/{key}/anonymous-users/assign", post(assign_anonymous_user).with_state(ctx.clone()))
+            .route("/share/{token}", post(open_share_link).with_state(ctx.clone()))
             .route("/add_user", post(add_user).with_state(ctx.clone()))
             .route("/find_or_create_user", post(find_or_create_user).with_state(ctx.clone()))
-            .route("/remove_user", post(remove_user).with_state(ctx.clone()));
+            .route("/remove_user", post(remove_user).with_state(ctx.clone()))
+            .route("/claim_user", post(claim_user).with_state(ctx.clone()))
+            .route("/request_claim", post(request_claim).with_state(ctx.clone()))
+            .route("/merge_users", post(merge_users).with_state(ctx.clone()))
+            .route("/{key}/claims", get(claims).with_state(ctx.clone()))
+            .route("/{key}/claims/approve", post(approve_claim).with_state(ctx.clone()))
+            .route("/{key}/claims/reject", post(reject_claim).with_state(ctx.clone()));
         Ok(router)
     }
 }
@@ -36,6 +76,9 @@ async fn create(
     request: Request,
 ) -> Result<impl IntoResponse, ServerError> {
     let user = require_connected_user!(request);
+    if ctx.config.backend_config.email_verification.required_for_calendar_creation && !user.email_verified {
+        return Err(ServerError::msg(StatusCode::FORBIDDEN, "Email address is not verified"));
+    }
 
     #[derive(Deserialize)]
     pub struct CreateCalendarData {
@@ -47,6 +90,8 @@ async fn create(
         end_daily_hour: i64,
         require_account: bool,
         default_presence: f32,
+        #[serde(default)]
+        default_role: CalendarRole,
     }
 
     let key = EncString::from("todo");
@@ -68,27 +113,772 @@ async fn create(
     calendar.end_daily_hour = calendar_data.end_daily_hour.clone();
     calendar.require_account = calendar_data.require_account;
     calendar.default_presence = calendar_data.default_presence;
+    calendar.default_role = calendar_data.default_role;
+    validate_calendar(&calendar)?;
     Calendar::push(&mut calendar, &ctx.database).await?;
     Ok(Json(calendar))
 }
 
-/// Get repositories owned by connected user
+fn validate_calendar(calendar: &Calendar) -> Result<(), ServerError> {
+    if calendar.title.is_empty() {
+        return Err(ServerError::msg(StatusCode::BAD_REQUEST, "Title cannot be empty"));
+    }
+    if calendar.end_date <= calendar.start_date {
+        return Err(ServerError::msg(StatusCode::BAD_REQUEST, "End date should be after start date"));
+    }
+    if calendar.time_precision <= 0 || calendar.time_precision > ONE_DAY_MS {
+        return Err(ServerError::msg(StatusCode::BAD_REQUEST, "Time precision should be between 0 and 24 hours"));
+    }
+    if calendar.start_daily_hour < 0 || calendar.end_daily_hour > ONE_DAY_MS || calendar.start_daily_hour >= calendar.end_daily_hour {
+        return Err(ServerError::msg(StatusCode::BAD_REQUEST, "Invalid daily hours"));
+    }
+    if !(-10.0..=10.0).contains(&calendar.default_presence) {
+        return Err(ServerError::msg(StatusCode::BAD_REQUEST, "Default presence should be between -10 and 10"));
+    }
+    if calendar.default_role == CalendarRole::Owner {
+        return Err(ServerError::msg(StatusCode::BAD_REQUEST, "Visitors cannot be owners of the calendar"));
+    }
+    Ok(())
+}
+
+/// Edit the settings of a calendar. Events that are not inside the new date range, or that do not fit in the new daily hours
+/// and time slots, can optionally be clipped or deleted.
+async fn update(
+    State(ctx): State<Arc<AppCtx>>,
+    Path(key): Path<EncString>,
+    request: Request,
+) -> Result<impl IntoResponse, ServerError> {
+    let user = require_connected_user!(request);
+
+    #[derive(Deserialize, Default, PartialEq)]
+    #[serde(rename_all = "lowercase")]
+    pub enum OutOfRangeEvents {
+        #[default]
+        Keep,
+        Clip,
+        Delete,
+    }
+
+    #[derive(Deserialize)]
+    pub struct UpdateCalendarData {
+        title: Option<EncString>,
+        start: Option<i64>,
+        end: Option<i64>,
+        time_precision: Option<i64>,
+        start_daily_hour: Option<i64>,
+        end_daily_hour: Option<i64>,
+        require_account: Option<bool>,
+        default_presence: Option<f32>,
+        default_role: Option<CalendarRole>,
+        #[serde(default)]
+        out_of_range_events: OutOfRangeEvents,
+        /// Offset in ms of the local time used to compute the daily hours of the events
+        #[serde(default)]
+        utc_offset: i64,
+    }
+
+    let data = Json::<UpdateCalendarData>::from_request(request, &ctx).await?.0;
+
+    let mut calendar = Calendar::from_key(&ctx.database, &key).await
+        .map_err(|err| ServerError::msg(StatusCode::NOT_FOUND, err))?;
+    check_calendar_permission(&ctx.database, &calendar, &Visitor::from(user), CalendarRole::Owner).await?;
+    let previous_slots = (calendar.time_precision, calendar.start_daily_hour, calendar.end_daily_hour);
+
+    if let Some(title) = data.title {
+        calendar.title = title;
+    }
+    if let Some(start) = data.start {
+        calendar.start_date = start;
+    }
+    if let Some(end) = data.end {
+        calendar.end_date = end;
+    }
+    if let Some(time_precision) = data.time_precision {
+        calendar.time_precision = time_precision;
+    }
+    if let Some(start_daily_hour) = data.start_daily_hour {
+        calendar.start_daily_hour = start_daily_hour;
+    }
+    if let Some(end_daily_hour) = data.end_daily_hour {
+        calendar.end_daily_hour = end_daily_hour;
+    }
+    if let Some(require_account) = data.require_account {
+        calendar.require_account = require_account;
+    }
+    if let Some(default_presence) = data.default_presence {
+        calendar.default_presence = default_presence;
+    }
+    if let Some(default_role) = data.default_role {
+        calendar.default_role = default_role;
+    }
+    validate_calendar(&calendar)?;
+    let slots_changed = previous_slots != (calendar.time_precision, calendar.start_daily_hour, calendar.end_daily_hour);
+
+    #[derive(Serialize, Default)]
+    pub struct UpdateSummary {
+        calendar: Calendar,
+        /// Events that are not entirely inside the calendar range, or that do not fit in its daily hours and time slots
+        out_of_range: usize,
+        clipped: usize,
+        deleted: usize,
+        /// Anonymous calendar users that are left in a calendar requiring an account
+        anonymous_users: usize,
+    }
+    let mut summary = UpdateSummary::default();
+    let mut live_events = vec![];
+
+    let tx = ctx.database.transaction().await?;
+    for mut event in Event::from_calendar(&tx, calendar.id()).await? {
+        let mut fully_outside = match &event.recurrence {
+            None => event.end_time <= calendar.start_date || event.start_time >= calendar.end_date,
+            Some(recurrence) => match Recurrence::from_str(recurrence) {
+                Ok(rule) => rule.occurrences(event.start_time, event.end_time - event.start_time, &event.exdates, &series_timezone(&event), calendar.start_date, calendar.end_date).is_empty(),
+                Err(_) => false,
+            },
+        };
+        let mut partially_outside = event.recurrence.is_none() && (event.start_time < calendar.start_date || event.end_time > calendar.end_date);
+        // Part of the event that is left once clipped. The occurrences of recurring events share the times of day of the series.
+        let (mut start, mut end) = match &event.recurrence {
+            None => (event.start_time.max(calendar.start_date), event.end_time.min(calendar.end_date)),
+            Some(_) => (event.start_time, event.end_time),
+        };
+        if slots_changed && !fully_outside {
+            match fit_in_slots(&calendar, start, end, data.utc_offset) {
+                None => fully_outside = true,
+                Some(fitted) => {
+                    partially_outside |= fitted != (start, end);
+                    (start, end) = fitted;
+                }
+            }
+        }
+        if !fully_outside && !partially_outside {
+            continue;
+        }
+        summary.out_of_range += 1;
+        match data.out_of_range_events {
+            OutOfRangeEvents::Keep => {}
+            OutOfRangeEvents::Clip if !fully_outside => {
+                event.start_time = start;
+                event.end_time = end;
+                event.push(&tx).await?;
+                live_events.push(LiveEvent::EventUpdated(event));
+                summary.clipped += 1;
+            }
+            OutOfRangeEvents::Clip | OutOfRangeEvents::Delete => {
+                event.delete(&tx).await?;
+                live_events.push(LiveEvent::EventDeleted(event.id().clone()));
+                summary.deleted += 1;
+            }
+        }
+    }
+
+    if calendar.require_account {
+        summary.anonymous_users = CalendarUser::from_anonymous(&tx, calendar.id()).await?.len();
+    }
+
+    Calendar::push(&mut calendar, &tx).await?;
+    tx.commit().await?;
+
+    for event in live_events {
+        ctx.live.publish(calendar.id(), event);
+    }
+    ctx.live.publish(calendar.id(), LiveEvent::CalendarUpdated(calendar.clone()));
+    summary.calendar = calendar;
+    Ok(Json(summary))
+}
+
+/// Get the calendars the connected user owns or is a member of
 async fn my_calendars(State(ctx): State<Arc<AppCtx>>, request: Request) -> impl IntoResponse {
     let user = require_connected_user!(request);
-    Ok(Json(Calendar::from_user(&ctx.database, user.id()).await?))
+    Ok(Json(Calendar::from_member(&ctx.database, user.id()).await?))
 }
 
 async fn get_calendar(
     State(ctx): State<Arc<AppCtx>>,
     Path(path): Path<EncString>,
+    request: Request,
 ) -> Result<impl IntoResponse, ServerError> {
+    let visitor = get_visitor!(request);
+
     #[derive(Serialize)]
     pub struct CalendarData {
         calendar: Calendar,
         users: Vec<CalendarUser>,
+        /// Role of the connected user
+        role: CalendarRole,
+    }
+    let mut calendar = find_calendar(&ctx.database, &path, &visitor).await?;
+    let role = check_calendar_permission(&ctx.database, &calendar, &visitor, CalendarRole::Viewer).await?;
+    // Visitors coming through a share link only get to know its token
+    calendar.key = path;
+    Ok(Json(CalendarData { users: CalendarUser::from_calendar(&ctx.database, calendar.id()).await?, calendar, role }))
+}
+
+/// Get the presence of every calendar user for each time slot of the calendar
+async fn availability(
+    State(ctx): State<Arc<AppCtx>>,
+    Path(key): Path<EncString>,
+    Query(params): Query<AvailabilityParams>,
+    request: Request,
+) -> Result<impl IntoResponse, ServerError> {
+    let visitor = get_visitor!(request);
+
+    let calendar = find_calendar(&ctx.database, &key, &visitor).await?;
+    check_calendar_permission(&ctx.database, &calendar, &visitor, CalendarRole::Viewer).await?;
+
+    let from = params.from.unwrap_or(calendar.start_date).max(calendar.start_date);
+    let to = params.to.unwrap_or(calendar.end_date).min(calendar.end_date);
+    let slots = calendar_slots(&calendar, from, to, params.utc_offset.unwrap_or(0))
+        .map_err(|err| ServerError::msg(StatusCode::BAD_REQUEST, err))?;
+
+    let users = CalendarUser::from_calendar(&ctx.database, calendar.id()).await?;
+    let events = expand_events(Event::from_calendar(&ctx.database, calendar.id()).await?, from, to);
+    Ok(Json(compute_availability(&calendar, &users, &events, &slots)))
+}
+
+#[derive(Deserialize)]
+pub struct AvailabilityParams {
+    from: Option<i64>,
+    to: Option<i64>,
+    utc_offset: Option<i64>,
+}
+
+/// Find the best time windows of a given duration to gather the calendar users
+async fn suggest(
+    State(ctx): State<Arc<AppCtx>>,
+    Path(key): Path<EncString>,
+    Query(params): Query<SuggestParams>,
+    request: Request,
+) -> Result<impl IntoResponse, ServerError> {
+    let visitor = get_visitor!(request);
+
+    let calendar = find_calendar(&ctx.database, &key, &visitor).await?;
+    check_calendar_permission(&ctx.database, &calendar, &visitor, CalendarRole::Viewer).await?;
+
+    if params.duration <= 0 {
+        return Err(ServerError::msg(StatusCode::BAD_REQUEST, "Duration must be positive"));
+    }
+
+    let users = CalendarUser::from_calendar(&ctx.database, calendar.id()).await?;
+    let mut required = vec![];
+    if let Some(required_users) = &params.required {
+        for id in required_users.split(',').filter(|id| !id.is_empty()) {
+            let id = CalendarUserId::from(DatabaseId::from_str(id)
+                .map_err(|err| ServerError::msg(StatusCode::BAD_REQUEST, format!("Invalid user id '{id}' : {err}")))?);
+            if !users.iter().any(|user| *user.id() == id) {
+                return Err(ServerError::msg(StatusCode::NOT_FOUND, format!("User {id} is not part of this calendar")));
+            }
+            required.push(id);
+        }
+    }
+
+    let from = params.from.unwrap_or(calendar.start_date).max(calendar.start_date);
+    let to = params.to.unwrap_or(calendar.end_date).min(calendar.end_date);
+    let slots = calendar_slots(&calendar, from, to, params.utc_offset.unwrap_or(0))
+        .map_err(|err| ServerError::msg(StatusCode::BAD_REQUEST, err))?;
+    let events = expand_events(Event::from_calendar(&ctx.database, calendar.id()).await?, from, to);
+    let availability = compute_availability(&calendar, &users, &events, &slots);
+
+    Ok(Json(suggest_windows(&availability, &SuggestOptions {
+        duration: params.duration,
+        count: params.count.unwrap_or(5),
+        min_participants: params.min_participants.unwrap_or(0),
+        required,
+        threshold: params.threshold.unwrap_or(0.0),
+    })))
+}
+
+#[derive(Deserialize)]
+pub struct SuggestParams {
+    duration: i64,
+    count: Option<usize>,
+    min_participants: Option<usize>,
+    /// Comma separated list of calendar user ids
+    required: Option<String>,
+    threshold: Option<f32>,
+    from: Option<i64>,
+    to: Option<i64>,
+    utc_offset: Option<i64>,
+}
+
+/// Classify each day of the calendar as free, partially free or busy, for every calendar user and overall
+async fn free_days_list(
+    State(ctx): State<Arc<AppCtx>>,
+    Path(key): Path<EncString>,
+    Query(params): Query<FreeDaysParams>,
+    request: Request,
+) -> Result<impl IntoResponse, ServerError> {
+    let visitor = get_visitor!(request);
+
+    let calendar = find_calendar(&ctx.database, &key, &visitor).await?;
+    check_calendar_permission(&ctx.database, &calendar, &visitor, CalendarRole::Viewer).await?;
+
+    let options = FreeDaysOptions {
+        threshold: params.threshold.unwrap_or(0.0),
+        free_ratio: params.free_ratio.unwrap_or(1.0),
+        busy_ratio: params.busy_ratio.unwrap_or(0.0),
+        utc_offset: params.utc_offset.unwrap_or(0),
+    };
+    if options.busy_ratio >= options.free_ratio {
+        return Err(ServerError::msg(StatusCode::BAD_REQUEST, "busy_ratio should be lower than free_ratio"));
+    }
+
+    let slots = calendar_slots(&calendar, calendar.start_date, calendar.end_date, options.utc_offset)
+        .map_err(|err| ServerError::msg(StatusCode::BAD_REQUEST, err))?;
+    let users = CalendarUser::from_calendar(&ctx.database, calendar.id()).await?;
+    let events = expand_events(Event::from_calendar(&ctx.database, calendar.id()).await?, calendar.start_date, calendar.end_date);
+    let days = free_days(&compute_availability(&calendar, &users, &events, &slots), &options);
+
+    if params.format.as_deref() != Some("csv") {
+        return Ok(Json(days).into_response());
+    }
+
+    let status = |status: DayStatus| match status {
+        DayStatus::Free => "free",
+        DayStatus::Partial => "partial",
+        DayStatus::Busy => "busy",
+    };
+    let mut csv = String::from("date,overall");
+    for user in &users {
+        csv += &format!(",\"{}\"", user.name.plain()?.replace('"', "\"\""));
+    }
+    csv += "\n";
+    for day in days {
+        csv += &format!("{},{}", day.date, status(day.status));
+        for user in day.users {
+            csv += &format!(",{}", status(user.status));
+        }
+        csv += "\n";
+    }
+    Ok(([(header::CONTENT_TYPE, "text/csv; charset=utf-8")], csv).into_response())
+}
+
+#[derive(Deserialize)]
+pub struct FreeDaysParams {
+    threshold: Option<f32>,
+    free_ratio: Option<f32>,
+    busy_ratio: Option<f32>,
+    utc_offset: Option<i64>,
+    /// 'json' (default) or 'csv'
+    format: Option<String>,
+}
+
+/// Stream the modifications of a calendar as server-sent events.
+/// Clients can resume after a reconnection with the 'since' parameter or the Last-Event-ID header.
+/// A 'reset' event is sent when the missed modifications are not available anymore.
+async fn live(
+    State(ctx): State<Arc<AppCtx>>,
+    Path(key): Path<EncString>,
+    Query(params): Query<LiveParams>,
+    request: Request,
+) -> Result<impl IntoResponse, ServerError> {
+    let visitor = get_visitor!(request);
+
+    let calendar = find_calendar(&ctx.database, &key, &visitor).await?;
+    check_calendar_permission(&ctx.database, &calendar, &visitor, CalendarRole::Viewer).await?;
+    // Visitors coming through a share link only get to know its token
+    let link_token = (calendar.key.encoded() != key.encoded()).then_some(key);
+
+    let since = params.since.or(request.headers().get("last-event-id")
+        .and_then(|header| header.to_str().ok())
+        .map(|header| header.to_string()));
+    let subscription = ctx.live.subscribe(calendar.id(), since.as_deref())
+        .ok_or(ServerError::msg(StatusCode::INTERNAL_SERVER_ERROR, "Live updates are not available"))?;
+
+    let mut last_seq = subscription.since;
+    let mut initial = vec![];
+    if subscription.reset {
+        initial.push(Ok(SseEvent::default().event("reset").data("reset")));
+    }
+    for message in subscription.missed {
+        last_seq = message.seq;
+        initial.push(SseEvent::default().id(ctx.live.event_id(&message)).json_data(hide_calendar_key(message, &link_token)));
+    }
+
+    let stream = tokio_stream::iter(initial).chain(BroadcastStream::new(subscription.receiver).filter_map(move |message| {
+        match message {
+            // Skip the messages that were already sent from the history
+            Ok(message) if message.seq <= last_seq => None,
+            Ok(message) => Some(SseEvent::default().id(ctx.live.event_id(&message)).json_data(hide_calendar_key(message, &link_token))),
+            Err(BroadcastStreamRecvError::Lagged(_)) => Some(Ok(SseEvent::default().event("reset").data("reset"))),
+        }
+    }));
+    Ok(Sse::new(stream).keep_alive(KeepAlive::default()))
+}
+
+/// Replace the primary key of the calendar sent in a live message with the token of the share link used by the visitor
+fn hide_calendar_key(mut message: LiveMessage, link_token: &Option<EncString>) -> LiveMessage {
+    if let (Some(token), LiveEvent::CalendarUpdated(calendar)) = (link_token, &mut message.event) {
+        calendar.key = token.clone();
+    }
+    message
+}
+
+#[derive(Deserialize)]
+pub struct LiveParams {
+    /// Id of the last received event
+    since: Option<String>,
+}
+
+/// List the users having an explicit role on a calendar
+async fn members(
+    State(ctx): State<Arc<AppCtx>>,
+    Path(key): Path<EncString>,
+    request: Request,
+) -> Result<impl IntoResponse, ServerError> {
+    let user = require_connected_user!(request);
+
+    let calendar = Calendar::from_key(&ctx.database, &key).await
+        .map_err(|err| ServerError::msg(StatusCode::NOT_FOUND, err))?;
+    check_calendar_permission(&ctx.database, &calendar, &Visitor::from(user), CalendarRole::Editor).await?;
+
+    #[derive(Serialize)]
+    pub struct MemberData {
+        #[serde(flatten)]
+        member: CalendarMember,
+        display_name: EncString,
+    }
+    let mut members = vec![];
+    for member in CalendarMember::from_calendar(&ctx.database, calendar.id()).await? {
+        let display_name = User::from_id(&ctx.database, &member.user_id).await?.display_name;
+        members.push(MemberData { member, display_name });
+    }
+    Ok(Json(members))
+}
+
+/// Give a role on a calendar to the user owning the given email address
+async fn set_member(
+    State(ctx): State<Arc<AppCtx>>,
+    Path(key): Path<EncString>,
+    request: Request,
+) -> Result<impl IntoResponse, ServerError> {
+    let user = require_connected_user!(request);
+
+    #[derive(Deserialize)]
+    pub struct MemberData {
+        email: EncString,
+        role: CalendarRole,
+    }
+    let data = Json::<MemberData>::from_request(request, &ctx).await?;
+
+    let calendar = Calendar::from_key(&ctx.database, &key).await
+        .map_err(|err| ServerError::msg(StatusCode::NOT_FOUND, err))?;
+    check_calendar_permission(&ctx.database, &calendar, &Visitor::from(user), CalendarRole::Owner).await?;
+
+    let member = User::from_email(&ctx.database, &data.email).await
+        .map_err(|err| ServerError::msg(StatusCode::NOT_FOUND, err))?;
+    if calendar.owner_id == *member.id() {
+        return Err(ServerError::msg(StatusCode::FORBIDDEN, "The role of the calendar owner cannot be changed"));
+    }
+    let member = CalendarMember { calendar_id: calendar.id().clone(), user_id: member.id().clone(), role: data.role };
+    member.push(&ctx.database).await?;
+    Ok(Json(member))
+}
+
+/// Remove the explicit role of a user on a calendar. The user falls back to the default role of the calendar.
+async fn remove_member(
+    State(ctx): State<Arc<AppCtx>>,
+    Path(key): Path<EncString>,
+    request: Request,
+) -> Result<impl IntoResponse, ServerError> {
+    let user = require_connected_user!(request);
+    let removed = Json::<UserId>::from_request(request, &ctx).await?;
+
+    let calendar = Calendar::from_key(&ctx.database, &key).await
+        .map_err(|err| ServerError::msg(StatusCode::NOT_FOUND, err))?;
+    check_calendar_permission(&ctx.database, &calendar, &Visitor::from(user), CalendarRole::Owner).await?;
+
+    if calendar.owner_id == *removed {
+        return Err(ServerError::msg(StatusCode::FORBIDDEN, "The calendar owner cannot be removed"));
+    }
+    let member = CalendarMember::from_user(&ctx.database, calendar.id(), &removed).await?
+        .ok_or(ServerError::msg(StatusCode::NOT_FOUND, "This user is not a member of this calendar"))?;
+    member.delete(&ctx.database).await?;
+    Ok(Json(member))
+}
+
+/// List the share links of a calendar
+async fn share_links(
+    State(ctx): State<Arc<AppCtx>>,
+    Path(key): Path<EncString>,
+    request: Request,
+) -> Result<impl IntoResponse, ServerError> {
+    let visitor = get_visitor!(request);
+
+    let calendar = find_calendar(&ctx.database, &key, &visitor).await?;
+    check_calendar_permission(&ctx.database, &calendar, &visitor, CalendarRole::Owner).await?;
+    Ok(Json(CalendarShareLink::from_calendar(&ctx.database, calendar.id()).await?))
+}
+
+/// Create a link giving access to the calendar with the given role
+async fn create_share_link(
+    State(ctx): State<Arc<AppCtx>>,
+    Path(key): Path<EncString>,
+    request: Request,
+) -> Result<impl IntoResponse, ServerError> {
+    let visitor = get_visitor!(request);
+
+    #[derive(Deserialize)]
+    pub struct CreateShareLinkData {
+        scope: CalendarRole,
+        /// Expiration date in seconds
+        expdate: Option<i64>,
+        password: Option<EncString>,
+    }
+    let data = Json::<CreateShareLinkData>::from_request(request, &ctx).await?;
+
+    let calendar = find_calendar(&ctx.database, &key, &visitor).await?;
+    check_calendar_permission(&ctx.database, &calendar, &visitor, CalendarRole::Owner).await?;
+
+    if data.scope == CalendarRole::Owner {
+        return Err(ServerError::msg(StatusCode::BAD_REQUEST, "Share links cannot give the owner role"));
+    }
+    if let Some(password) = &data.password {
+        if password.is_empty() {
+            return Err(ServerError::msg(StatusCode::BAD_REQUEST, "Password cannot be empty"));
+        }
     }
-    let calendar = Calendar::from_key(&ctx.database, &path).await?;
-    Ok(Json(CalendarData { users: CalendarUser::from_calendar(&ctx.database, calendar.id()).await?, calendar }))
+    Ok(Json(CalendarShareLink::create(&ctx.database, calendar.id(), data.scope, data.expdate, data.password.as_ref()).await?))
+}
+
+async fn revoke_share_link(
+    State(ctx): State<Arc<AppCtx>>,
+    Path(key): Path<EncString>,
+    request: Request,
+) -> Result<impl IntoResponse, ServerError> {
+    let visitor = get_visitor!(request);
+    let id = Json::<ShareLinkId>::from_request(request, &ctx).await?;
+
+    let calendar = find_calendar(&ctx.database, &key, &visitor).await?;
+    check_calendar_permission(&ctx.database, &calendar, &visitor, CalendarRole::Owner).await?;
+
+    let link = CalendarShareLink::from_id(&ctx.database, &id).await
+        .map_err(|err| ServerError::msg(StatusCode::NOT_FOUND, err))?;
+    if link.calendar_id != *calendar.id() {
+        return Err(ServerError::msg(StatusCode::NOT_FOUND, "Share link not found"));
+    }
+    link.delete(&ctx.database).await?;
+    Ok(Json(link))
+}
+
+/// Open a share link. The role of the link is never persisted : visitors, connected or not, should send the returned bearer
+/// in the 'content-share-token' header of their next requests, so revoked or expired links stop granting access.
+/// The token of the link is then accepted in place of the calendar key by the calendar routes.
+async fn open_share_link(
+    State(ctx): State<Arc<AppCtx>>,
+    Path(token): Path<EncString>,
+    request: Request,
+) -> Result<impl IntoResponse, ServerError> {
+    let visitor = get_visitor!(request);
+    let ip = client_ip(&ctx, &request);
+
+    #[derive(Deserialize, Default)]
+    pub struct OpenShareLinkData {
+        password: Option<EncString>,
+    }
+    let data = Json::<OpenShareLinkData>::from_request(request, &ctx).await.map(|data| data.0).unwrap_or_default();
+
+    let mut link = CalendarShareLink::from_token(&ctx.database, &token).await
+        .map_err(|err| ServerError::msg(StatusCode::NOT_FOUND, err))?;
+
+    // Only failed password attempts are counted, per client, so a link shared with many people can't be locked by one of them
+    let client = match (ip, &visitor.user) {
+        (Some(ip), _) => format!("ip/{ip}"),
+        (None, Some(user)) => format!("user/{}", user.id()),
+        (None, None) => String::from("unknown"),
+    };
+    let limiter_key = format!("share/{}/{client}", link.id());
+    ctx.rate_limiter.check_lock(&limiter_key).map_err(ServerError::too_many_requests)?;
+    if !link.check_password(data.password.as_ref())? {
+        ctx.rate_limiter.failure(&limiter_key, &ctx.config.backend_config.rate_limit);
+        return Err(ServerError::msg(StatusCode::FORBIDDEN, "Invalid password"));
+    }
+    ctx.rate_limiter.success(&limiter_key);
+
+    let mut calendar = Calendar::from_id(&ctx.database, &link.calendar_id).await
+        .map_err(|err| ServerError::msg(StatusCode::NOT_FOUND, err))?;
+    link.increment_uses(&ctx.database).await?;
+    calendar.key = link.token.clone();
+
+    #[derive(Serialize)]
+    pub struct OpenedShareLink {
+        role: CalendarRole,
+        bearer: EncString,
+        /// The key of the calendar is replaced with the token of the link : the primary key would give access without the link
+        calendar: Calendar,
+    }
+    let visitor = Visitor { user: visitor.user, share_link: Some(link.clone()) };
+    Ok(Json(OpenedShareLink {
+        role: check_calendar_permission(&ctx.database, &calendar, &visitor, CalendarRole::Viewer).await?,
+        bearer: link.bearer().clone(),
+        calendar,
+    }))
+}
+
+/// Replace the primary key of a calendar and its feed token. Clients using the previous key are disconnected from the live updates.
+async fn rotate_key(
+    State(ctx): State<Arc<AppCtx>>,
+    Path(key): Path<EncString>,
+    request: Request,
+) -> Result<impl IntoResponse, ServerError> {
+    let visitor = get_visitor!(request);
+
+    let mut calendar = find_calendar(&ctx.database, &key, &visitor).await?;
+    check_calendar_permission(&ctx.database, &calendar, &visitor, CalendarRole::Owner).await?;
+    calendar.rotate_key(&ctx.database).await?;
+    if CalendarFeed::from_calendar(&ctx.database, calendar.id()).await?.is_some() {
+        CalendarFeed::generate(&ctx.database, calendar.id()).await?;
+    }
+    ctx.live.close(calendar.id());
+    Ok(Json(calendar))
+}
+
+/// List the calendar users that are not bound to an account
+async fn anonymous_users(
+    State(ctx): State<Arc<AppCtx>>,
+    Path(key): Path<EncString>,
+    request: Request,
+) -> Result<impl IntoResponse, ServerError> {
+    let visitor = get_visitor!(request);
+
+    let calendar = find_calendar(&ctx.database, &key, &visitor).await?;
+    check_calendar_permission(&ctx.database, &calendar, &visitor, CalendarRole::Editor).await?;
+    Ok(Json(CalendarUser::from_anonymous(&ctx.database, calendar.id()).await?))
+}
+
+/// Bind an anonymous calendar user to the account owning the given email address
+async fn assign_anonymous_user(
+    State(ctx): State<Arc<AppCtx>>,
+    Path(key): Path<EncString>,
+    request: Request,
+) -> Result<impl IntoResponse, ServerError> {
+    let visitor = get_visitor!(request);
+
+    #[derive(Deserialize)]
+    pub struct AssignData {
+        user: CalendarUserId,
+        email: EncString,
+    }
+    let data = Json::<AssignData>::from_request(request, &ctx).await?;
+
+    let calendar = find_calendar(&ctx.database, &key, &visitor).await?;
+    check_calendar_permission(&ctx.database, &calendar, &visitor, CalendarRole::Editor).await?;
+
+    let mut calendar_user = CalendarUser::from_id(&ctx.database, &data.user).await
+        .map_err(|err| ServerError::msg(StatusCode::NOT_FOUND, err))?;
+    if calendar_user.calendar_id != *calendar.id() {
+        return Err(ServerError::msg(StatusCode::NOT_FOUND, "This user is not part of this calendar"));
+    }
+    if calendar_user.user_id.is_some() {
+        return Err(ServerError::msg(StatusCode::BAD_REQUEST, "This user is already bound to an account"));
+    }
+    let account = User::from_email(&ctx.database, &data.email).await
+        .map_err(|err| ServerError::msg(StatusCode::NOT_FOUND, err))?;
+    if CalendarUser::from_user(&ctx.database, calendar.id(), account.id()).await.is_ok() {
+        return Err(ServerError::msg(StatusCode::CONFLICT, "This account already has a calendar user in this calendar"));
+    }
+
+    calendar_user.user_id = Some(account.id().clone());
+    calendar_user.push(&ctx.database).await?;
+    ctx.live.publish(calendar.id(), LiveEvent::UserUpdated(calendar_user.clone()));
+    Ok(Json(calendar_user))
+}
+
+/// Get the secret token used to subscribe to the ics feed of a calendar
+async fn feed_token(
+    State(ctx): State<Arc<AppCtx>>,
+    Path(key): Path<EncString>,
+    request: Request,
+) -> Result<impl IntoResponse, ServerError> {
+    let visitor = get_visitor!(request);
+
+    let calendar = find_calendar(&ctx.database, &key, &visitor).await?;
+    check_calendar_permission(&ctx.database, &calendar, &visitor, CalendarRole::Editor).await?;
+
+    Ok(Json(match CalendarFeed::from_calendar(&ctx.database, calendar.id()).await? {
+        None => CalendarFeed::generate(&ctx.database, calendar.id()).await?,
+        Some(feed) => feed,
+    }))
+}
+
+/// Replace the feed token of a calendar, invalidating existing subscriptions
+async fn rotate_feed_token(
+    State(ctx): State<Arc<AppCtx>>,
+    Path(key): Path<EncString>,
+    request: Request,
+) -> Result<impl IntoResponse, ServerError> {
+    let user = require_connected_user!(request);
+
+    let calendar = Calendar::from_key(&ctx.database, &key).await
+        .map_err(|err| ServerError::msg(StatusCode::NOT_FOUND, err))?;
+    check_calendar_permission(&ctx.database, &calendar, &Visitor::from(user), CalendarRole::Owner).await?;
+    Ok(Json(CalendarFeed::generate(&ctx.database, calendar.id()).await?))
+}
+
+/// Export the events of a calendar as an ics feed, optionally filtered on a single calendar user
+async fn feed(
+    State(ctx): State<Arc<AppCtx>>,
+    Path(key): Path<EncString>,
+    Query(params): Query<FeedParams>,
+) -> Result<impl IntoResponse, ServerError> {
+    let calendar = Calendar::from_key(&ctx.database, &key).await
+        .map_err(|err| ServerError::msg(StatusCode::NOT_FOUND, err))?;
+    CalendarFeed::find(&ctx.database, calendar.id(), &params.token).await
+        .map_err(|err| ServerError::msg(StatusCode::FORBIDDEN, err))?;
+
+    let users = CalendarUser::from_calendar(&ctx.database, calendar.id()).await?;
+    if let Some(filter) = &params.user {
+        if !users.iter().any(|user| user.id() == filter) {
+            return Err(ServerError::msg(StatusCode::NOT_FOUND, format!("User {filter} is not part of this calendar")));
+        }
+    }
+
+    let mut writer = IcsWriter::new(&calendar.title.plain()?);
+    for event in Event::from_calendar(&ctx.database, calendar.id()).await? {
+        if let Some(filter) = &params.user {
+            if event.owner != *filter {
+                continue;
+            }
+        }
+        let participant = match users.iter().find(|user| *user.id() == event.owner) {
+            None => String::new(),
+            Some(user) => user.name.plain()?,
+        };
+        let category = if event.presence > 0.0 {
+            "AVAILABLE"
+        } else if event.presence < 0.0 {
+            "BUSY"
+        } else {
+            "NEUTRAL"
+        };
+        let mut properties = vec![
+            ("CATEGORIES", category.to_string()),
+            ("X-SCHEDULATOR-PRESENCE", event.presence.to_string()),
+            ("X-SCHEDULATOR-PARTICIPANT", escape_text(&participant)),
+        ];
+        if let Some(recurrence) = &event.recurrence {
+            properties.push(("RRULE", recurrence.clone()));
+            if !event.exdates.is_empty() {
+                properties.push(("EXDATE", event.exdates.iter().map(|date| format_date_time(*date)).collect::<Vec<String>>().join(",")));
+            }
+        }
+        writer.add_event(
+            &format!("{}@schedulator", event.id()),
+            event.start_time,
+            event.end_time,
+            &series_timezone(&event),
+            &event.title.plain()?,
+            &properties,
+        );
+    }
+
+    Ok(([(header::CONTENT_TYPE, "text/calendar; charset=utf-8")], writer.finish()))
+}
+
+#[derive(Deserialize)]
+pub struct FeedParams {
+    token: EncString,
+    user: Option<CalendarUserId>,
 }
 
 /// Delete repository
@@ -105,16 +895,13 @@ async fn delete(
 
     let data = Json::<RequestParams>::from_request(request, &ctx).await?;
 
-    for calendar in Calendar::from_user(&ctx.database, connected_user.id()).await? {
-        if calendar.key.encoded() == data.calendar_key.encoded() {
-            calendar.delete(&ctx.database).await?;
-            return Ok(Json(vec![calendar.id().clone()]));
-        }
-    }
-    return Err(ServerError::msg(
-        StatusCode::FORBIDDEN,
-        "You don't own this calendar",
-    ));
+    let calendar = Calendar::from_key(&ctx.database, &data.calendar_key).await
+        .map_err(|err| ServerError::msg(StatusCode::NOT_FOUND, err))?;
+    check_calendar_permission(&ctx.database, &calendar, &Visitor::from(connected_user), CalendarRole::Owner).await?;
+    calendar.delete(&ctx.database).await?;
+    ctx.live.publish(calendar.id(), LiveEvent::CalendarDeleted(calendar.id().clone()));
+    ctx.live.close(calendar.id());
+    Ok(Json(vec![calendar.id().clone()]))
 }
 
 /// Get all root items of a repository
@@ -129,6 +916,9 @@ pub async fn find_or_create_user(
         pub calendar: CalendarId,
     }
     let data = Json::<RequestParams>::from_request(request, &ctx).await?;
+    let calendar = Calendar::from_id(&ctx.database, &data.calendar).await
+        .map_err(|err| ServerError::msg(StatusCode::NOT_FOUND, err))?;
+    check_calendar_permission(&ctx.database, &calendar, &Visitor::from(user.clone()), CalendarRole::Participant).await?;
 
     if let Ok(found) = CalendarUser::from_user(&ctx.database, &data.calendar, user.id()).await {
         return Ok(Json(found));
@@ -139,6 +929,7 @@ pub async fn find_or_create_user(
     calendar_user.user_id = Some(user.id().clone());
     calendar_user.calendar_id = data.calendar.clone();
     CalendarUser::push(&mut calendar_user, &ctx.database).await?;
+    ctx.live.publish(&calendar_user.calendar_id, LiveEvent::UserAdded(calendar_user.clone()));
     Ok(Json(calendar_user))
 }
 
@@ -153,18 +944,29 @@ pub async fn add_user(
         calendar: CalendarId,
     }
 
-    let mut user = None;
-    get_connected_user!(request, found_user, user = Some(found_user.clone()));
-    let user_id = match &user {
-        None => None,
-        Some(user) => Some(user.id().clone()),
-    };
+    let visitor = get_visitor!(request);
 
     let data = Json::<CreateUserData>::from_request(request, &ctx).await?;
 
     if data.name.is_empty() {
         return Err(ServerError::msg(StatusCode::NOT_ACCEPTABLE, "Name cannot be empty"));
     }
+    let calendar = Calendar::from_id(&ctx.database, &data.calendar).await
+        .map_err(|err| ServerError::msg(StatusCode::NOT_FOUND, err))?;
+    let role = check_calendar_permission(&ctx.database, &calendar, &visitor, CalendarRole::Participant).await?;
+
+    // The new calendar user is bound to the account of the caller. Editors can add other participants as anonymous users.
+    let user_id = match &visitor.user {
+        None => None,
+        Some(user) => match CalendarUser::from_user(&ctx.database, calendar.id(), user.id()).await {
+            Err(_) => Some(user.id().clone()),
+            Ok(_) if role >= CalendarRole::Editor && !calendar.require_account => None,
+            Ok(_) => return Err(ServerError::msg(
+                StatusCode::CONFLICT,
+                "You already have a calendar user in this calendar",
+            )),
+        },
+    };
 
     if CalendarUser::from_username(&ctx.database, &data.calendar, &data.name).await.is_ok() {
         return Err(ServerError::msg(
@@ -178,7 +980,12 @@ pub async fn add_user(
     calendar_user.user_id = user_id;
     calendar_user.calendar_id = data.calendar.clone();
     CalendarUser::push(&mut calendar_user, &ctx.database).await?;
-    Ok(Json(calendar_user))
+    let claim_secret = match calendar_user.user_id {
+        None => Some(calendar_user.generate_claim_secret(&ctx.database).await?),
+        Some(_) => None,
+    };
+    ctx.live.publish(&calendar_user.calendar_id, LiveEvent::UserAdded(calendar_user.clone()));
+    Ok(Json(NewCalendarUser { calendar_user, claim_secret }))
 }
 
 /// Get trash root items of a repository
@@ -186,22 +993,194 @@ pub async fn remove_user(
     State(ctx): State<Arc<AppCtx>>,
     request: axum::http::Request<Body>,
 ) -> Result<impl IntoResponse, ServerError> {
-    let owner = require_connected_user!(request);
+    let visitor = get_visitor!(request);
     let data = Json::<Vec<CalendarUserId>>::from_request(request, &ctx).await?;
 
     for removed in &data.0 {
-        let calendar_user = CalendarUser::from_id(&ctx.database, &removed).await?;
+        let calendar_user = CalendarUser::from_id(&ctx.database, &removed).await
+            .map_err(|err| ServerError::msg(StatusCode::NOT_FOUND, err))?;
         let calendar = Calendar::from_id(&ctx.database, &calendar_user.calendar_id).await?;
-
-        if calendar.owner_id != *owner.id() {
-            return Err(ServerError::msg(
-                StatusCode::FORBIDDEN,
-                "Forbidden : not owning this calendar",
-            ));
-        }
+        check_calendar_permission(&ctx.database, &calendar, &visitor, CalendarRole::Editor).await?;
 
         calendar_user.delete(&ctx.database).await?;
+        ctx.live.publish(calendar.id(), LiveEvent::UserRemoved(calendar_user.id().clone()));
     }
 
     Ok(Json(data.0))
 }
+
+/// Find an anonymous calendar user that can be claimed by the connected user
+async fn find_claimable_user(ctx: &AppCtx, user: &User, id: &CalendarUserId) -> Result<(Calendar, CalendarUser), ServerError> {
+    let calendar_user = CalendarUser::from_id(&ctx.database, id).await
+        .map_err(|err| ServerError::msg(StatusCode::NOT_FOUND, err))?;
+    if calendar_user.user_id.is_some() {
+        return Err(ServerError::msg(StatusCode::BAD_REQUEST, "This user is already bound to an account"));
+    }
+    let calendar = Calendar::from_id(&ctx.database, &calendar_user.calendar_id).await
+        .map_err(|err| ServerError::msg(StatusCode::NOT_FOUND, err))?;
+    check_calendar_permission(&ctx.database, &calendar, &Visitor::from(user.clone()), CalendarRole::Participant).await?;
+    Ok((calendar, calendar_user))
+}
+
+/// Bind an anonymous calendar user to an account and notify the clients
+async fn apply_claim(ctx: &AppCtx, calendar: &Calendar, claimed: CalendarUser, account: &UserId) -> Result<CalendarUser, ServerError> {
+    let claimed_id = claimed.id().clone();
+    let result = claimed.claim(&ctx.database, account).await?;
+    if *result.target.id() == claimed_id {
+        ctx.live.publish(calendar.id(), LiveEvent::UserUpdated(result.target.clone()));
+        Ok(result.target)
+    } else {
+        // The account already had a calendar user : the events were merged into it
+        Ok(publish_merge(ctx, calendar, &claimed_id, result).target)
+    }
+}
+
+fn publish_merge(ctx: &AppCtx, calendar: &Calendar, source: &CalendarUserId, result: MergeResult) -> MergeResult {
+    for event in &result.deleted {
+        ctx.live.publish(calendar.id(), LiveEvent::EventDeleted(event.clone()));
+    }
+    for event in &result.moved {
+        ctx.live.publish(calendar.id(), LiveEvent::EventUpdated(event.clone()));
+    }
+    ctx.live.publish(calendar.id(), LiveEvent::UserRemoved(source.clone()));
+    ctx.live.publish(calendar.id(), LiveEvent::UserUpdated(result.target.clone()));
+    result
+}
+
+/// Bind an anonymous calendar user to the connected account using the secret given when it was created
+async fn claim_user(
+    State(ctx): State<Arc<AppCtx>>,
+    request: Request,
+) -> Result<impl IntoResponse, ServerError> {
+    let user = require_connected_user!(request);
+
+    #[derive(Deserialize)]
+    pub struct ClaimData {
+        calendar_user: CalendarUserId,
+        secret: EncString,
+    }
+    let data = Json::<ClaimData>::from_request(request, &ctx).await?;
+
+    let (calendar, calendar_user) = find_claimable_user(&ctx, &user, &data.calendar_user).await?;
+    if !calendar_user.check_claim_secret(&ctx.database, &data.secret).await? {
+        return Err(ServerError::msg(StatusCode::FORBIDDEN, "Invalid claim secret"));
+    }
+    Ok(Json(apply_claim(&ctx, &calendar, calendar_user, user.id()).await?))
+}
+
+/// Ask the calendar owner to bind an anonymous calendar user to the connected account
+async fn request_claim(
+    State(ctx): State<Arc<AppCtx>>,
+    request: Request,
+) -> Result<impl IntoResponse, ServerError> {
+    let user = require_connected_user!(request);
+    let id = Json::<CalendarUserId>::from_request(request, &ctx).await?;
+
+    let (_, calendar_user) = find_claimable_user(&ctx, &user, &id).await?;
+    Ok(Json(CalendarUserClaim::create(&ctx.database, calendar_user.id(), user.id()).await?))
+}
+
+/// List the pending claims of a calendar
+async fn claims(
+    State(ctx): State<Arc<AppCtx>>,
+    Path(key): Path<EncString>,
+    request: Request,
+) -> Result<impl IntoResponse, ServerError> {
+    let visitor = get_visitor!(request);
+
+    let calendar = find_calendar(&ctx.database, &key, &visitor).await?;
+    check_calendar_permission(&ctx.database, &calendar, &visitor, CalendarRole::Owner).await?;
+
+    #[derive(Serialize)]
+    pub struct ClaimData {
+        #[serde(flatten)]
+        claim: CalendarUserClaim,
+        display_name: EncString,
+    }
+    let mut claims = vec![];
+    for claim in CalendarUserClaim::from_calendar(&ctx.database, calendar.id()).await? {
+        let display_name = User::from_id(&ctx.database, &claim.user_id).await?.display_name;
+        claims.push(ClaimData { claim, display_name });
+    }
+    Ok(Json(claims))
+}
+
+#[derive(Deserialize)]
+pub struct ClaimDecision {
+    calendar_user: CalendarUserId,
+    user: UserId,
+}
+
+/// Find a pending claim of a calendar the visitor owns
+async fn find_claim(ctx: &AppCtx, visitor: &Visitor, key: &EncString, decision: &ClaimDecision) -> Result<(Calendar, CalendarUserClaim), ServerError> {
+    let calendar = find_calendar(&ctx.database, key, visitor).await?;
+    check_calendar_permission(&ctx.database, &calendar, visitor, CalendarRole::Owner).await?;
+    let claim = CalendarUserClaim::find(&ctx.database, &decision.calendar_user, &decision.user).await
+        .map_err(|err| ServerError::msg(StatusCode::NOT_FOUND, err))?;
+    let calendar_user = CalendarUser::from_id(&ctx.database, &claim.calendar_user_id).await
+        .map_err(|err| ServerError::msg(StatusCode::NOT_FOUND, err))?;
+    if calendar_user.calendar_id != *calendar.id() {
+        return Err(ServerError::msg(StatusCode::NOT_FOUND, "Claim not found"));
+    }
+    Ok((calendar, claim))
+}
+
+async fn approve_claim(
+    State(ctx): State<Arc<AppCtx>>,
+    Path(key): Path<EncString>,
+    request: Request,
+) -> Result<impl IntoResponse, ServerError> {
+    let visitor = get_visitor!(request);
+    let data = Json::<ClaimDecision>::from_request(request, &ctx).await?;
+
+    let (calendar, claim) = find_claim(&ctx, &visitor, &key, &data).await?;
+    let calendar_user = CalendarUser::from_id(&ctx.database, &claim.calendar_user_id).await
+        .map_err(|err| ServerError::msg(StatusCode::NOT_FOUND, err))?;
+    if calendar_user.user_id.is_some() {
+        claim.delete(&ctx.database).await?;
+        return Err(ServerError::msg(StatusCode::BAD_REQUEST, "This user is already bound to an account"));
+    }
+    Ok(Json(apply_claim(&ctx, &calendar, calendar_user, &claim.user_id).await?))
+}
+
+async fn reject_claim(
+    State(ctx): State<Arc<AppCtx>>,
+    Path(key): Path<EncString>,
+    request: Request,
+) -> Result<impl IntoResponse, ServerError> {
+    let visitor = get_visitor!(request);
+    let data = Json::<ClaimDecision>::from_request(request, &ctx).await?;
+
+    let (_, claim) = find_claim(&ctx, &visitor, &key, &data).await?;
+    claim.delete(&ctx.database).await?;
+    Ok(Json(claim))
+}
+
+/// Merge a calendar user into another one : the events are moved and the source user is removed
+async fn merge_users(
+    State(ctx): State<Arc<AppCtx>>,
+    request: Request,
+) -> Result<impl IntoResponse, ServerError> {
+    let visitor = get_visitor!(request);
+
+    #[derive(Deserialize)]
+    pub struct MergeData {
+        source: CalendarUserId,
+        target: CalendarUserId,
+        #[serde(default)]
+        keep_on_conflict: KeepOnConflict,
+    }
+    let data = Json::<MergeData>::from_request(request, &ctx).await?;
+
+    let source = CalendarUser::from_id(&ctx.database, &data.source).await
+        .map_err(|err| ServerError::msg(StatusCode::NOT_FOUND, err))?;
+    let target = CalendarUser::from_id(&ctx.database, &data.target).await
+        .map_err(|err| ServerError::msg(StatusCode::NOT_FOUND, err))?;
+    let calendar = Calendar::from_id(&ctx.database, &target.calendar_id).await
+        .map_err(|err| ServerError::msg(StatusCode::NOT_FOUND, err))?;
+    check_calendar_permission(&ctx.database, &calendar, &visitor, CalendarRole::Owner).await?;
+
+    let result = source.merge_into(&ctx.database, target, data.keep_on_conflict).await
+        .map_err(|err| ServerError::msg(StatusCode::BAD_REQUEST, err))?;
+    Ok(Json(publish_merge(&ctx, &calendar, source.id(), result)))
+}
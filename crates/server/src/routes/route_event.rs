@@ -1,14 +1,25 @@
+use crate::database::calendar::Calendar;
+use crate::database::calendar_members::CalendarRole;
+use crate::database::calendar_users::CalendarUser;
 use crate::database::event::Event;
+use crate::get_visitor;
+use crate::ics::parser::parse_events;
 use crate::routes::app_ctx::AppCtx;
+use crate::routes::live_updates::LiveEvent;
+use crate::routes::permissions::{check_calendar_permission, check_calendar_user_write, Visitor};
+use crate::scheduling::recurrence::{expand_occurrences, Recurrence};
 use crate::server_error::ServerError;
-use crate::types::database_ids::{CalendarId, CalendarUserId, EventId};
+use crate::types::database_ids::{CalendarId, CalendarUserId, DatabaseIdTrait, EventId};
 use crate::types::enc_string::EncString;
 use anyhow::Error;
-use axum::extract::{FromRequest, Request, State};
+use axum::extract::{FromRequest, Multipart, Path, Request, State};
+use axum::http::StatusCode;
 use axum::response::IntoResponse;
-use axum::routing::post;
+use axum::routing::{patch, post};
 use axum::{Json, Router};
-use serde::Deserialize;
+use chrono_tz::Tz;
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
 use std::sync::Arc;
 
 pub struct EventRoutes {}
@@ -18,7 +29,10 @@ impl EventRoutes {
         let router = Router::new()
             .route("/create", post(create_event).with_state(ctx.clone()))
             .route("/from-calendar", post(from_calendar).with_state(ctx.clone()))
-            .route("/delete", post(delete_event).with_state(ctx.clone()));
+            .route("/delete", post(delete_event).with_state(ctx.clone()))
+            .route("/import-ics", post(import_ics).with_state(ctx.clone()))
+            .route("/update", post(update_events).with_state(ctx.clone()))
+            .route("/{id}", patch(update_event).with_state(ctx.clone()));
         Ok(router)
     }
 }
@@ -36,13 +50,42 @@ async fn create_event(
         start: i64,
         end: i64,
         source: EncString,
-        presence: f32
+        presence: f32,
+        recurrence: Option<String>,
+        #[serde(default)]
+        exdates: Vec<i64>,
+        /// Time zone of recurring events, UTC if none
+        timezone: Option<String>,
     }
 
+    let visitor = get_visitor!(request);
+
     let data = Json::<Vec<CreateEventData>>::from_request(request, &ctx).await?;
 
+    for event in &data.0 {
+        let calendar = Calendar::from_id(&ctx.database, &event.calendar).await
+            .map_err(|err| ServerError::msg(StatusCode::NOT_FOUND, err))?;
+        let calendar_user = CalendarUser::from_id(&ctx.database, &event.owner).await
+            .map_err(|err| ServerError::msg(StatusCode::NOT_FOUND, err))?;
+        check_calendar_user_write(&ctx.database, &calendar, &calendar_user, &visitor).await?;
+        if event.end <= event.start {
+            return Err(ServerError::msg(StatusCode::BAD_REQUEST, "Event would end before it starts"));
+        }
+        if event.start < calendar.start_date || event.end > calendar.end_date {
+            return Err(ServerError::msg(StatusCode::BAD_REQUEST, "Event would be outside of the calendar range"));
+        }
+        if let Some(recurrence) = &event.recurrence {
+            Recurrence::from_str(recurrence)
+                .map_err(|err| ServerError::msg(StatusCode::BAD_REQUEST, format!("Invalid recurrence : {err}")))?;
+        }
+        if let Some(timezone) = &event.timezone {
+            Tz::from_str(timezone).map_err(|_| ServerError::msg(StatusCode::BAD_REQUEST, format!("Unknown timezone {timezone}")))?;
+        }
+    }
+
     let mut events = vec![];
 
+    let tx = ctx.database.transaction().await?;
     for event in data.0 {
         let mut new_event = Event::default();
         new_event.calendar = event.calendar.clone();
@@ -52,11 +95,21 @@ async fn create_event(
         new_event.end_time = event.end.clone();
         new_event.source = event.source.clone();
         new_event.presence = event.presence;
+        new_event.recurrence = match &event.recurrence {
+            None => None,
+            Some(recurrence) => Some(Recurrence::from_str(recurrence)?.to_string()),
+        };
+        new_event.exdates = event.exdates;
+        new_event.timezone = event.timezone;
 
-        new_event.push(&ctx.database).await?;
+        new_event.push(&tx).await?;
         events.push(new_event);
     }
+    tx.commit().await?;
 
+    for event in &events {
+        ctx.live.publish(&event.calendar, LiveEvent::EventCreated(event.clone()));
+    }
     Ok(Json(events))
 }
 
@@ -64,20 +117,300 @@ async fn from_calendar(
     State(ctx): State<Arc<AppCtx>>,
     request: Request,
 ) -> Result<impl IntoResponse, ServerError> {
+    let visitor = get_visitor!(request);
+
     let data = Json::<CalendarId>::from_request(request, &ctx).await?;
-    Ok(Json(Event::from_calendar(&ctx.database, &data).await?))
+    let calendar = Calendar::from_id(&ctx.database, &data).await
+        .map_err(|err| ServerError::msg(StatusCode::NOT_FOUND, err))?;
+    check_calendar_permission(&ctx.database, &calendar, &visitor, CalendarRole::Viewer).await?;
+    let events = Event::from_calendar(&ctx.database, calendar.id()).await?;
+    Ok(Json(expand_occurrences(events, calendar.start_date, calendar.end_date)))
 }
 
 async fn delete_event(
     State(ctx): State<Arc<AppCtx>>,
     request: Request,
 ) -> Result<impl IntoResponse, ServerError> {
+    let visitor = get_visitor!(request);
 
     let data = Json::<Vec<EventId>>::from_request(request, &ctx).await?;
 
-    for event in data.0 {
-        Event::from_id(&ctx.database, &event).await?.delete(&ctx.database).await?;
+    let mut events = vec![];
+    for event in &data.0 {
+        let event = Event::from_id(&ctx.database, event).await
+            .map_err(|err| ServerError::msg(StatusCode::NOT_FOUND, err))?;
+        let calendar = Calendar::from_id(&ctx.database, &event.calendar).await
+            .map_err(|err| ServerError::msg(StatusCode::NOT_FOUND, err))?;
+        let calendar_user = CalendarUser::from_id(&ctx.database, &event.owner).await
+            .map_err(|err| ServerError::msg(StatusCode::NOT_FOUND, err))?;
+        check_calendar_user_write(&ctx.database, &calendar, &calendar_user, &visitor).await?;
+        events.push(event);
     }
 
+    let tx = ctx.database.transaction().await?;
+    for event in &events {
+        event.delete(&tx).await?;
+    }
+    tx.commit().await?;
+
+    for event in events {
+        ctx.live.publish(&event.calendar, LiveEvent::EventDeleted(event.id().clone()));
+    }
     Ok(())
-}
\ No newline at end of file
+}
+#[derive(Deserialize)]
+struct EventUpdate {
+    id: EventId,
+    title: Option<EncString>,
+    owner: Option<CalendarUserId>,
+    start: Option<i64>,
+    end: Option<i64>,
+    presence: Option<f32>,
+    /// Move the event by the given amount of ms
+    offset: Option<i64>,
+    /// Resize the event by moving its end by the given amount of ms
+    end_offset: Option<i64>,
+}
+
+/// Edit a single event
+async fn update_event(
+    State(ctx): State<Arc<AppCtx>>,
+    Path(id): Path<EventId>,
+    request: Request,
+) -> Result<impl IntoResponse, ServerError> {
+    let visitor = get_visitor!(request);
+
+    #[derive(Deserialize)]
+    struct UpdateEventData {
+        title: Option<EncString>,
+        owner: Option<CalendarUserId>,
+        start: Option<i64>,
+        end: Option<i64>,
+        presence: Option<f32>,
+    }
+    let data = Json::<UpdateEventData>::from_request(request, &ctx).await?.0;
+
+    let mut events = apply_event_updates(&ctx, &visitor, vec![EventUpdate {
+        id,
+        title: data.title,
+        owner: data.owner,
+        start: data.start,
+        end: data.end,
+        presence: data.presence,
+        offset: None,
+        end_offset: None,
+    }]).await?;
+    Ok(Json(events.pop()))
+}
+
+/// Edit, move or resize multiple events at once. Either every event is updated or none is.
+async fn update_events(
+    State(ctx): State<Arc<AppCtx>>,
+    request: Request,
+) -> Result<impl IntoResponse, ServerError> {
+    let visitor = get_visitor!(request);
+
+    let data = Json::<Vec<EventUpdate>>::from_request(request, &ctx).await?;
+    Ok(Json(apply_event_updates(&ctx, &visitor, data.0).await?))
+}
+
+async fn apply_event_updates(ctx: &AppCtx, visitor: &Visitor, updates: Vec<EventUpdate>) -> Result<Vec<Event>, ServerError> {
+    let mut events = vec![];
+    for update in updates {
+        let mut event = Event::from_id(&ctx.database, &update.id).await
+            .map_err(|err| ServerError::msg(StatusCode::NOT_FOUND, err))?;
+        let calendar = Calendar::from_id(&ctx.database, &event.calendar).await
+            .map_err(|err| ServerError::msg(StatusCode::NOT_FOUND, err))?;
+        let calendar_user = CalendarUser::from_id(&ctx.database, &event.owner).await
+            .map_err(|err| ServerError::msg(StatusCode::NOT_FOUND, err))?;
+        check_calendar_user_write(&ctx.database, &calendar, &calendar_user, visitor).await?;
+
+        if let Some(owner) = update.owner {
+            if owner != event.owner {
+                let new_owner = CalendarUser::from_id(&ctx.database, &owner).await
+                    .map_err(|err| ServerError::msg(StatusCode::NOT_FOUND, err))?;
+                check_calendar_user_write(&ctx.database, &calendar, &new_owner, visitor).await?;
+                event.owner = owner;
+            }
+        }
+        if let Some(title) = update.title {
+            event.title = title;
+        }
+        if let Some(presence) = update.presence {
+            event.presence = presence;
+        }
+
+        let (start, end) = (event.start_time, event.end_time);
+        if let Some(start) = update.start {
+            event.start_time = start;
+        }
+        if let Some(end) = update.end {
+            event.end_time = end;
+        }
+        let id = event.id().clone();
+        let out_of_range = || ServerError::msg(StatusCode::BAD_REQUEST, format!("Event {id} would be moved out of the time range"));
+        if let Some(offset) = update.offset {
+            event.start_time = event.start_time.checked_add(offset).ok_or_else(out_of_range)?;
+            event.end_time = event.end_time.checked_add(offset).ok_or_else(out_of_range)?;
+        }
+        if let Some(end_offset) = update.end_offset {
+            event.end_time = event.end_time.checked_add(end_offset).ok_or_else(out_of_range)?;
+        }
+        if event.end_time <= event.start_time {
+            return Err(ServerError::msg(StatusCode::BAD_REQUEST, format!("Event {} would end before it starts", event.id())));
+        }
+        if (event.start_time != start || event.end_time != end) && (event.start_time < calendar.start_date || event.end_time > calendar.end_date) {
+            return Err(ServerError::msg(StatusCode::BAD_REQUEST, format!("Event {} would be outside of the calendar range", event.id())));
+        }
+        events.push(event);
+    }
+
+    let tx = ctx.database.transaction().await?;
+    for event in &mut events {
+        event.push(&tx).await?;
+    }
+    tx.commit().await?;
+
+    for event in &events {
+        ctx.live.publish(&event.calendar, LiveEvent::EventUpdated(event.clone()));
+    }
+    Ok(events)
+}
+
+/// Import the events of an ics file for a calendar user.
+/// Events previously imported from a file with the same name are replaced.
+async fn import_ics(
+    State(ctx): State<Arc<AppCtx>>,
+    request: Request,
+) -> Result<impl IntoResponse, ServerError> {
+    let visitor = get_visitor!(request);
+
+    let mut multipart = Multipart::from_request(request, &ctx).await?;
+
+    let mut calendar = None;
+    let mut owner = None;
+    let mut presence = -10.0;
+    let mut timezone = Tz::UTC;
+    let mut file = None;
+    while let Some(field) = multipart.next_field().await.map_err(|err| ServerError::msg(StatusCode::BAD_REQUEST, err))? {
+        let name = field.name().unwrap_or_default().to_string();
+        match name.as_str() {
+            "file" => {
+                let file_name = field.file_name().unwrap_or("unknown.ics").to_string();
+                let data = field.text().await.map_err(|err| ServerError::msg(StatusCode::BAD_REQUEST, err))?;
+                file = Some((file_name, data));
+            }
+            _ => {
+                let value = field.text().await.map_err(|err| ServerError::msg(StatusCode::BAD_REQUEST, err))?;
+                match name.as_str() {
+                    "calendar" => calendar = Some(CalendarId::from(i64::from_str(&value).map_err(|err| ServerError::msg(StatusCode::BAD_REQUEST, err))?)),
+                    "owner" => owner = Some(CalendarUserId::from(i64::from_str(&value).map_err(|err| ServerError::msg(StatusCode::BAD_REQUEST, err))?)),
+                    "presence" => presence = f32::from_str(&value).map_err(|err| ServerError::msg(StatusCode::BAD_REQUEST, err))?,
+                    "timezone" => timezone = Tz::from_str(&value).map_err(|_| ServerError::msg(StatusCode::BAD_REQUEST, format!("Unknown timezone {value}")))?,
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    let calendar = calendar.ok_or(ServerError::msg(StatusCode::BAD_REQUEST, "Missing calendar field"))?;
+    let owner = owner.ok_or(ServerError::msg(StatusCode::BAD_REQUEST, "Missing owner field"))?;
+    let (file_name, data) = file.ok_or(ServerError::msg(StatusCode::BAD_REQUEST, "Missing file field"))?;
+
+    let calendar = Calendar::from_id(&ctx.database, &calendar).await
+        .map_err(|err| ServerError::msg(StatusCode::NOT_FOUND, err))?;
+    let calendar_user = CalendarUser::from_id(&ctx.database, &owner).await
+        .map_err(|err| ServerError::msg(StatusCode::NOT_FOUND, err))?;
+    check_calendar_user_write(&ctx.database, &calendar, &calendar_user, &visitor).await?;
+
+    #[derive(Serialize, Default)]
+    struct ImportResult {
+        added: usize,
+        updated: usize,
+        removed: usize,
+        skipped: usize,
+        events: Vec<Event>,
+    }
+    let mut result = ImportResult::default();
+
+    let source = EncString::from(format!("import@{file_name}"));
+    let mut live_events = vec![];
+
+    let tx = ctx.database.transaction().await?;
+    let mut previous_events = Event::from_source_for_update(&tx, calendar_user.id(), &source).await?;
+
+    for parsed in parse_events(&data, &timezone) {
+        let parsed = match parsed {
+            Ok(parsed) => parsed,
+            Err(_) => {
+                result.skipped += 1;
+                continue;
+            }
+        };
+        let (start, end) = match &parsed.recurrence {
+            None => (parsed.start.max(calendar.start_date), parsed.end.min(calendar.end_date)),
+            Some(recurrence) => {
+                // Recurring events are kept whole as long as one of their occurrences is inside the calendar
+                // The TZID was already checked when parsing the dates
+                let series_timezone = parsed.timezone.as_deref().and_then(|timezone| Tz::from_str(timezone).ok()).unwrap_or(Tz::UTC);
+                match Recurrence::from_str(recurrence) {
+                    Ok(rule) if !rule.occurrences(parsed.start, parsed.end - parsed.start, &parsed.exdates, &series_timezone, calendar.start_date, calendar.end_date).is_empty() => (parsed.start, parsed.end),
+                    _ => {
+                        result.skipped += 1;
+                        continue;
+                    }
+                }
+            }
+        };
+        if end <= start {
+            result.skipped += 1;
+            continue;
+        }
+
+        let mut event = match previous_events.iter().position(|event| event.start_time == start && event.end_time == end) {
+            Some(index) => {
+                result.updated += 1;
+                previous_events.swap_remove(index)
+            }
+            None => {
+                result.added += 1;
+                let mut event = Event::default();
+                event.calendar = calendar.id().clone();
+                event.owner = calendar_user.id().clone();
+                event.start_time = start;
+                event.end_time = end;
+                event.source = source.clone();
+                event
+            }
+        };
+        event.title = EncString::from(parsed.title);
+        event.presence = presence;
+        event.recurrence = match &parsed.recurrence {
+            None => None,
+            Some(recurrence) => Some(Recurrence::from_str(recurrence)?.to_string()),
+        };
+        event.exdates = parsed.exdates;
+        event.timezone = parsed.timezone;
+        let created = !event.id().is_valid();
+        event.push(&tx).await?;
+        live_events.push(if created {
+            LiveEvent::EventCreated(event.clone())
+        } else {
+            LiveEvent::EventUpdated(event.clone())
+        });
+        result.events.push(event);
+    }
+
+    // Remove the events of the previous import that are not in the file anymore
+    for event in previous_events {
+        event.delete(&tx).await?;
+        live_events.push(LiveEvent::EventDeleted(event.id().clone()));
+        result.removed += 1;
+    }
+    tx.commit().await?;
+
+    for event in live_events {
+        ctx.live.publish(calendar.id(), event);
+    }
+    Ok(Json(result))
+}
@@ -1,20 +1,24 @@
-use crate::database::auth_token::AuthToken;
+use crate::database::auth_token::{AuthToken, NewAuthToken, Session};
+use crate::database::email_changes::EmailChange;
+use crate::database::email_verifications::EmailVerification;
 use crate::database::reset_passwords::ResetPasswords;
 use crate::database::user::User;
 use crate::require_connected_user;
 use crate::routes::app_ctx::AppCtx;
+use crate::routes::{client_ip, RequestContext};
 use crate::server_error::ServerError;
-use crate::types::database_ids::PasswordHash;
+use crate::types::database_ids::{AuthTokenId, PasswordHash};
 use crate::types::enc_string::EncString;
 use anyhow::Error;
 use axum::extract::{FromRequest, Request, State};
-use axum::http::StatusCode;
-use axum::response::IntoResponse;
+use axum::http::{header, StatusCode};
+use axum::response::{IntoResponse, Response};
 use axum::routing::{get, post};
 use axum::{Json, Router};
 use axum_extra::extract::CookieJar;
 use serde::{Deserialize, Serialize};
 use std::sync::Arc;
+use tracing::warn;
 
 pub struct UserRoutes {}
 
@@ -35,18 +39,74 @@ impl UserRoutes {
                 "/forgot-password-update",
                 post(forgot_password_update).with_state(ctx.clone()),
             )
-            .route("/auth_tokens", get(auth_tokens).with_state(ctx.clone()))
+            .route("/verify-email", post(verify_email).with_state(ctx.clone()))
+            .route("/resend-verification", post(resend_verification).with_state(ctx.clone()))
+            .route("/change-password", post(change_password).with_state(ctx.clone()))
+            .route("/change-display-name", post(change_display_name).with_state(ctx.clone()))
+            .route("/change-email", post(change_email).with_state(ctx.clone()))
+            .route("/confirm-email", post(confirm_email).with_state(ctx.clone()))
+            .route("/sessions", get(sessions).with_state(ctx.clone()))
+            .route("/sessions/revoke", post(revoke_session).with_state(ctx.clone()))
+            .route("/sessions/revoke-others", post(revoke_other_sessions).with_state(ctx.clone()))
             .route("/logout", post(logout).with_state(ctx.clone()))
             .route("/delete", post(delete_user).with_state(ctx.clone()));
         Ok(router)
     }
 }
 
+/// Limiter keys of an attempt on a sensitive route
+struct RateLimitKeys {
+    /// Keys of the targeted accounts, or a single key shared by every unknown login
+    accounts: Vec<String>,
+    ip: Option<String>,
+}
+
+/// Count a request to a sensitive route for the client address and the targeted account.
+/// Logins are resolved to accounts so the email and the display name of an account share the same counter.
+/// Returns the limiter keys, used to report the result of the attempt.
+async fn check_rate_limit(ctx: &AppCtx, scope: &str, ip: Option<String>, login: &EncString) -> Result<RateLimitKeys, ServerError> {
+    let mut accounts: Vec<String> = User::from_login(&ctx.database, login, login).await?
+        .iter().map(|user| format!("{scope}/account/{}", user.id())).collect();
+    if accounts.is_empty() {
+        accounts.push(format!("{scope}/account/unknown"));
+    }
+    hit_rate_limit(ctx, scope, ip, accounts)
+}
+
+/// Count a request to a sensitive route for the client address and the given account keys.
+fn hit_rate_limit(ctx: &AppCtx, scope: &str, ip: Option<String>, accounts: Vec<String>) -> Result<RateLimitKeys, ServerError> {
+    let config = &ctx.config.backend_config.rate_limit;
+    for key in &accounts {
+        ctx.rate_limiter.hit(key, config.max_requests_per_account, config).map_err(ServerError::too_many_requests)?;
+    }
+    let ip = ip.map(|ip| format!("{scope}/ip/{ip}"));
+    if let Some(key) = &ip {
+        ctx.rate_limiter.hit(key, config.max_requests_per_ip, config).map_err(ServerError::too_many_requests)?;
+    }
+    Ok(RateLimitKeys { accounts, ip })
+}
+
+fn report_failure(ctx: &AppCtx, keys: &RateLimitKeys) {
+    for key in keys.accounts.iter().chain(&keys.ip) {
+        ctx.rate_limiter.failure(key, &ctx.config.backend_config.rate_limit);
+    }
+}
+
+/// Forget the failed attempts on the targeted accounts. The failures of the client address are kept, so a successful
+/// attempt on an account does not unlock an address trying other accounts.
+fn report_success(ctx: &AppCtx, keys: &RateLimitKeys) {
+    for key in &keys.accounts {
+        ctx.rate_limiter.success(key);
+    }
+}
+
 async fn forgot_password_create(
     State(ctx): State<Arc<AppCtx>>,
     request: Request,
 ) -> Result<impl IntoResponse, ServerError> {
+    let ip = client_ip(&ctx, &request);
     let payload = Json::<EncString>::from_request(request, &ctx).await?;
+    check_rate_limit(&ctx, "reset-create", ip, &payload).await?;
     let users = User::from_login(&ctx.database, &payload, &payload)
         .await
         .map_err(|err| {
@@ -72,16 +132,18 @@ async fn forgot_password_check(
         pub user: EncString,
         pub code: EncString,
     }
+    let ip = client_ip(&ctx, &request);
     let payload = Json::<Payload>::from_request(request, &ctx).await?;
+    let keys = check_rate_limit(&ctx, "reset", ip, &payload.user).await?;
     let users = User::from_login(&ctx.database, &payload.user, &payload.user)
         .await
         .map_err(|err| ServerError::msg(StatusCode::NOT_FOUND, err))?;
     for user in users {
-        match ResetPasswords::from_user(&ctx.database, user.id(), &payload.code.plain()?).await {
-            Ok(_) => return Ok(()),
-            Err(_) => {}
+        if ResetPasswords::from_user(&ctx.database, user.id(), &payload.code.plain()?, ctx.config.backend_config.rate_limit.reset_code_max_attempts).await.is_ok() {
+            return Ok(());
         }
     }
+    report_failure(&ctx, &keys);
     Err(ServerError::msg(StatusCode::NOT_FOUND, "Invalid code"))
 }
 async fn forgot_password_update(
@@ -94,20 +156,21 @@ async fn forgot_password_update(
         pub code: EncString,
         pub new_password: EncString,
     }
+    let ip = client_ip(&ctx, &request);
     let payload = Json::<Payload>::from_request(request, &ctx).await?;
+    let keys = check_rate_limit(&ctx, "reset", ip, &payload.login).await?;
     let users = User::from_login(&ctx.database, &payload.login, &payload.login)
         .await
         .map_err(|err| ServerError::msg(StatusCode::NOT_FOUND, err))?;
     for user in users {
-        match ResetPasswords::from_user(&ctx.database, user.id(), &payload.code.plain()?).await {
-            Ok(item) => {
-                item.reset_password(&ctx.database, &payload.new_password)
-                    .await?;
-                return Ok(());
-            }
-            Err(_) => {}
+        if let Ok(item) = ResetPasswords::from_user(&ctx.database, user.id(), &payload.code.plain()?, ctx.config.backend_config.rate_limit.reset_code_max_attempts).await {
+            item.reset_password(&ctx.database, &payload.new_password)
+                .await?;
+            report_success(&ctx, &keys);
+            return Ok(());
         }
     }
+    report_failure(&ctx, &keys);
     Err(ServerError::msg(StatusCode::NOT_FOUND, "Invalid code"))
 }
 
@@ -156,6 +219,11 @@ async fn create_user(
                 ))
             }
         };
+
+        // The account is kept even if the email could not be sent : the code can be sent again later
+        if let Err(err) = EmailVerification::create(&ctx.database, &ctx.config.backend_config.emailer, &ctx.config.backend_config.email_verification, &new_user).await {
+            warn!("Failed to send verification email : {err}");
+        }
     };
 
     Ok((StatusCode::OK, "Created new user".to_string()))
@@ -166,6 +234,9 @@ pub struct UserCredentials {
     login: EncString,
     password: EncString,
     device: Option<EncString>,
+    /// Keep the session open for a longer time
+    #[serde(default)]
+    remember_me: bool,
 }
 
 /// Get authentication token
@@ -173,22 +244,29 @@ async fn login(
     State(ctx): State<Arc<AppCtx>>,
     request: Request,
 ) -> Result<impl IntoResponse, ServerError> {
-    #[derive(Serialize, Deserialize)]
+    #[derive(Serialize)]
     pub struct LoginResult {
-        pub token: AuthToken,
+        pub token: NewAuthToken,
         pub user: User,
     }
 
+    let ip = client_ip(&ctx, &request);
     let payload = Json::<UserCredentials>::from_request(request, &ctx).await?;
+    let keys = check_rate_limit(&ctx, "login", ip.clone(), &payload.login).await?;
 
     let user = User::from_credentials(&ctx.database, &payload.login, &payload.password)
         .await
         .map_err(|err| {
+            report_failure(&ctx, &keys);
             ServerError::msg(
                 StatusCode::NOT_FOUND,
                 format!("Invalid credentials : {err}"),
             )
         })?;
+    report_success(&ctx, &keys);
+    if ctx.config.backend_config.email_verification.required_for_login && !user.email_verified {
+        return Err(ServerError::msg(StatusCode::FORBIDDEN, "Email address is not verified"));
+    }
     let auth_token = User::generate_auth_token(
         &user,
         &ctx.database,
@@ -196,6 +274,12 @@ async fn login(
             None => EncString::from("Unknown device"),
             Some(device) => device.clone(),
         },
+        if payload.remember_me {
+            ctx.config.backend_config.sessions.remember_me_lifetime
+        } else {
+            ctx.config.backend_config.sessions.short_lifetime
+        },
+        ip,
     )
     .await?;
 
@@ -205,17 +289,196 @@ async fn login(
     }))
 }
 
-/// Get authentication tokens for current account
-async fn auth_tokens(
+async fn verify_email(
     State(ctx): State<Arc<AppCtx>>,
     request: Request,
-) -> Result<Json<Vec<AuthToken>>, ServerError> {
+) -> Result<impl IntoResponse, ServerError> {
+    #[derive(Deserialize)]
+    pub struct Payload {
+        pub login: EncString,
+        pub code: EncString,
+    }
+    let ip = client_ip(&ctx, &request);
+    let payload = Json::<Payload>::from_request(request, &ctx).await?;
+    let keys = check_rate_limit(&ctx, "verify", ip, &payload.login).await?;
+    let users = User::from_login(&ctx.database, &payload.login, &payload.login)
+        .await
+        .map_err(|err| ServerError::msg(StatusCode::NOT_FOUND, err))?;
+    for mut user in users {
+        if EmailVerification::verify(&ctx.database, &mut user, &payload.code.plain()?).await.is_ok() {
+            report_success(&ctx, &keys);
+            return Ok(());
+        }
+    }
+    report_failure(&ctx, &keys);
+    Err(ServerError::msg(StatusCode::NOT_FOUND, "Invalid code"))
+}
+
+async fn resend_verification(
+    State(ctx): State<Arc<AppCtx>>,
+    request: Request,
+) -> Result<Response, ServerError> {
+    let payload = Json::<EncString>::from_request(request, &ctx).await?;
+    let users = User::from_login(&ctx.database, &payload, &payload)
+        .await
+        .map_err(|err| ServerError::msg(StatusCode::NOT_FOUND, err))?;
+    let user = users.into_iter().find(|user| !user.email_verified)
+        .ok_or(ServerError::msg(StatusCode::NOT_FOUND, "No account is waiting for a verification"))?;
+
+    let config = &ctx.config.backend_config.email_verification;
+    let cooldown = EmailVerification::cooldown(&ctx.database, config, user.id()).await?;
+    if cooldown > 0 {
+        return Ok((
+            StatusCode::TOO_MANY_REQUESTS,
+            [(header::RETRY_AFTER, cooldown.to_string())],
+            "A verification email was sent recently",
+        ).into_response());
+    }
+    EmailVerification::create(&ctx.database, &ctx.config.backend_config.emailer, config, &user).await?;
+    Ok(().into_response())
+}
+
+/// Check the password of the connected user before a sensitive change.
+/// Attempts share the limiter keys of the login, so the password cannot be guessed faster from here.
+async fn check_current_password(ctx: &AppCtx, ip: Option<String>, user: &User, password: &EncString) -> Result<(), ServerError> {
+    let keys = hit_rate_limit(ctx, "login", ip, vec![format!("login/account/{}", user.id())])?;
+    if user.check_password(password)? {
+        report_success(ctx, &keys);
+        Ok(())
+    } else {
+        report_failure(ctx, &keys);
+        Err(ServerError::msg(StatusCode::FORBIDDEN, "Invalid password"))
+    }
+}
+
+/// Update the password of current account. Other sessions are closed.
+async fn change_password(
+    State(ctx): State<Arc<AppCtx>>,
+    request: Request,
+) -> Result<impl IntoResponse, ServerError> {
+    #[derive(Deserialize)]
+    pub struct Payload {
+        pub current_password: EncString,
+        pub new_password: EncString,
+    }
+    let mut connected_user = require_connected_user!(request);
+    let current = current_session(&request);
+    let ip = client_ip(&ctx, &request);
+    let payload = Json::<Payload>::from_request(request, &ctx).await?;
+    check_current_password(&ctx, ip, &connected_user, &payload.current_password).await?;
+
+    let tx = ctx.database.transaction().await?;
+    User::create_or_reset_password(&mut connected_user, &tx, &PasswordHash::new(&payload.new_password)?).await?;
+    AuthToken::delete_from_user(&tx, connected_user.id(), current.as_ref()).await?;
+    tx.commit().await?;
+    Ok(())
+}
+
+async fn change_display_name(
+    State(ctx): State<Arc<AppCtx>>,
+    request: Request,
+) -> Result<impl IntoResponse, ServerError> {
+    #[derive(Deserialize)]
+    pub struct Payload {
+        pub password: EncString,
+        pub display_name: EncString,
+    }
+    let mut connected_user = require_connected_user!(request);
+    let ip = client_ip(&ctx, &request);
+    let payload = Json::<Payload>::from_request(request, &ctx).await?;
+    check_current_password(&ctx, ip, &connected_user, &payload.password).await?;
+
+    let url_name = payload.display_name.url_formated()?;
+    if url_name.is_empty() {
+        return Err(ServerError::msg(StatusCode::BAD_REQUEST, "Invalid name"));
+    }
+    if let Ok(user) = User::from_url_name(&ctx.database, &url_name).await {
+        if *user.id() != *connected_user.id() {
+            return Err(ServerError::msg(StatusCode::CONFLICT, "A user with the same name already exists !"));
+        }
+    }
+    connected_user.display_name = url_name;
+    User::push(&mut connected_user, &ctx.database).await?;
+    Ok(Json(connected_user))
+}
+
+/// Request an email change. It only takes effect once confirmed with the code sent to the new address.
+async fn change_email(
+    State(ctx): State<Arc<AppCtx>>,
+    request: Request,
+) -> Result<impl IntoResponse, ServerError> {
+    #[derive(Deserialize)]
+    pub struct Payload {
+        pub password: EncString,
+        pub email: EncString,
+    }
+    let connected_user = require_connected_user!(request);
+    let ip = client_ip(&ctx, &request);
+    let payload = Json::<Payload>::from_request(request, &ctx).await?;
+    check_current_password(&ctx, ip, &connected_user, &payload.password).await?;
+
+    if User::from_email(&ctx.database, &payload.email).await.is_ok() {
+        return Err(ServerError::msg(StatusCode::CONFLICT, "This email is already used"));
+    }
+    EmailChange::create(&ctx.database, &ctx.config.backend_config.emailer, &connected_user, &payload.email).await?;
+    Ok(())
+}
+
+async fn confirm_email(
+    State(ctx): State<Arc<AppCtx>>,
+    request: Request,
+) -> Result<impl IntoResponse, ServerError> {
+    let mut connected_user = require_connected_user!(request);
+    let code = Json::<EncString>::from_request(request, &ctx).await?;
+    EmailChange::confirm(&ctx.database, &mut connected_user, &code.plain()?).await
+        .map_err(|err| ServerError::msg(StatusCode::FORBIDDEN, err))?;
+    Ok(Json(connected_user))
+}
+
+/// Authentication token used by the current request
+fn current_session(request: &Request) -> Option<AuthTokenId> {
+    request.extensions().get::<Arc<RequestContext>>().and_then(|context| context.session.clone())
+}
+
+/// Get the opened sessions of current account
+async fn sessions(
+    State(ctx): State<Arc<AppCtx>>,
+    request: Request,
+) -> Result<Json<Vec<Session>>, ServerError> {
     let connected_user = require_connected_user!(request);
+    let current = current_session(&request);
     Ok(Json(
-        AuthToken::from_user(&ctx.database, connected_user.id()).await?,
+        AuthToken::from_user(&ctx.database, connected_user.id()).await?
+            .into_iter()
+            .map(|token| Session::new(token, current.as_ref()))
+            .collect(),
     ))
 }
 
+/// Close one of the sessions of current account
+async fn revoke_session(
+    State(ctx): State<Arc<AppCtx>>,
+    request: Request,
+) -> Result<impl IntoResponse, ServerError> {
+    let connected_user = require_connected_user!(request);
+    let id = Json::<AuthTokenId>::from_request(request, &ctx).await?;
+    let token = AuthToken::from_id(&ctx.database, connected_user.id(), &id).await
+        .map_err(|err| ServerError::msg(StatusCode::NOT_FOUND, err))?;
+    AuthToken::delete(&token, &ctx.database).await?;
+    Ok(())
+}
+
+/// Close every session of current account except the one used by this request
+async fn revoke_other_sessions(
+    State(ctx): State<Arc<AppCtx>>,
+    request: Request,
+) -> Result<impl IntoResponse, ServerError> {
+    let connected_user = require_connected_user!(request);
+    let current = current_session(&request);
+    let revoked = AuthToken::delete_from_user(&ctx.database, connected_user.id(), current.as_ref()).await?;
+    Ok(Json(revoked))
+}
+
 async fn delete_user(
     State(ctx): State<Arc<AppCtx>>,
     request: Request,
@@ -0,0 +1,119 @@
+use crate::database::calendar::Calendar;
+use crate::database::calendar_users::CalendarUser;
+use crate::database::event::Event;
+use crate::types::database_ids::CalendarUserId;
+use anyhow::Error;
+use serde::Serialize;
+
+pub const ONE_DAY_MS: i64 = 24 * 60 * 60 * 1000;
+
+#[derive(Serialize, Debug, Clone)]
+pub struct Slot {
+    pub start: i64,
+    pub end: i64,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct UserPresence {
+    pub user: CalendarUserId,
+    pub presence: f32,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct SlotAvailability {
+    pub start: i64,
+    pub end: i64,
+    pub presence: f32,
+    pub users: Vec<UserPresence>,
+}
+
+/// Split the [from, to[ range into slots of the calendar's time precision, keeping only the daily hours.
+/// utc_offset is the offset in ms of the local time used to compute day boundaries.
+pub fn calendar_slots(calendar: &Calendar, from: i64, to: i64, utc_offset: i64) -> Result<Vec<Slot>, Error> {
+    if calendar.time_precision <= 0 {
+        return Err(Error::msg("Invalid calendar time precision"));
+    }
+    if calendar.start_daily_hour < 0 || calendar.end_daily_hour > ONE_DAY_MS || calendar.start_daily_hour >= calendar.end_daily_hour {
+        return Err(Error::msg("Invalid calendar daily hours"));
+    }
+
+    let mut slots = vec![];
+    let mut day = (from + utc_offset).div_euclid(ONE_DAY_MS) * ONE_DAY_MS - utc_offset;
+    while day < to {
+        let day_end = day + calendar.end_daily_hour;
+        let mut start = day + calendar.start_daily_hour;
+        while start < day_end {
+            let end = (start + calendar.time_precision).min(day_end);
+            if end > from && start < to {
+                slots.push(Slot { start, end });
+            }
+            start = end;
+        }
+        day += ONE_DAY_MS;
+    }
+    Ok(slots)
+}
+
+/// Part of [start, end[ that fits in the daily hours of its day and on the time slots of the calendar, None if nothing is left.
+/// utc_offset is the offset in ms of the local time used to compute day boundaries.
+pub fn fit_in_slots(calendar: &Calendar, start: i64, end: i64, utc_offset: i64) -> Option<(i64, i64)> {
+    let day = (start + utc_offset).div_euclid(ONE_DAY_MS) * ONE_DAY_MS - utc_offset;
+    let day_start = day + calendar.start_daily_hour;
+    let day_end = day + calendar.end_daily_hour;
+    let start = start.max(day_start);
+    let end = end.min(day_end);
+    // The start is rounded up and the end down to the slot boundaries. The last slot of the day can be shorter.
+    let start = day_start + (start - day_start + calendar.time_precision - 1).div_euclid(calendar.time_precision) * calendar.time_precision;
+    let end = if end == day_end {
+        end
+    } else {
+        day_start + (end - day_start).div_euclid(calendar.time_precision) * calendar.time_precision
+    };
+    (end > start).then_some((start, end))
+}
+
+/// Presence of a user during the given slot. When several events overlap the slot, the lowest presence wins.
+fn user_presence(calendar: &Calendar, events: &[&Event], slot: &Slot) -> f32 {
+    let mut presence: Option<f32> = None;
+    for event in events {
+        if event.start_time >= slot.end {
+            break;
+        }
+        if event.end_time > slot.start {
+            presence = Some(match presence {
+                None => event.presence,
+                Some(presence) => presence.min(event.presence),
+            });
+        }
+    }
+    presence.unwrap_or(calendar.default_presence)
+}
+
+/// Compute the presence of each calendar user for every slot, along with the mean presence of the slot
+pub fn compute_availability(calendar: &Calendar, users: &[CalendarUser], events: &[Event], slots: &[Slot]) -> Vec<SlotAvailability> {
+    let user_events: Vec<Vec<&Event>> = users.iter().map(|user| {
+        let mut owned: Vec<&Event> = events.iter().filter(|event| event.owner == *user.id()).collect();
+        owned.sort_by_key(|event| event.start_time);
+        owned
+    }).collect();
+
+    slots.iter().map(|slot| {
+        let users: Vec<UserPresence> = users.iter().zip(&user_events).map(|(user, events)| {
+            UserPresence {
+                user: user.id().clone(),
+                presence: user_presence(calendar, events, slot),
+            }
+        }).collect();
+        let presence = if users.is_empty() {
+            calendar.default_presence
+        } else {
+            users.iter().map(|user| user.presence).sum::<f32>() / users.len() as f32
+        };
+        SlotAvailability {
+            start: slot.start,
+            end: slot.end,
+            presence,
+            users,
+        }
+    }).collect()
+}
@@ -0,0 +1,90 @@
+use crate::scheduling::availability::{SlotAvailability, ONE_DAY_MS};
+use crate::types::database_ids::CalendarUserId;
+use chrono::DateTime;
+use serde::Serialize;
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum DayStatus {
+    Free,
+    Partial,
+    Busy,
+}
+
+pub struct FreeDaysOptions {
+    /// A slot is free for a user when its presence is greater or equal to this threshold
+    pub threshold: f32,
+    /// Minimum ratio of free slots for a day to be considered free
+    pub free_ratio: f32,
+    /// Maximum ratio of free slots for a day to be considered busy
+    pub busy_ratio: f32,
+    /// Offset in ms of the local time used to compute day boundaries
+    pub utc_offset: i64,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct UserDay {
+    pub user: CalendarUserId,
+    pub status: DayStatus,
+    pub free_ratio: f32,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct FreeDay {
+    /// Local date formatted as YYYY-MM-DD
+    pub date: String,
+    /// Timestamp in ms of the local midnight
+    pub start: i64,
+    /// Status of the day when all the users should be free at the same time
+    pub status: DayStatus,
+    pub free_ratio: f32,
+    pub users: Vec<UserDay>,
+}
+
+impl FreeDaysOptions {
+    fn status(&self, free_ratio: f32) -> DayStatus {
+        if free_ratio >= self.free_ratio {
+            DayStatus::Free
+        } else if free_ratio <= self.busy_ratio {
+            DayStatus::Busy
+        } else {
+            DayStatus::Partial
+        }
+    }
+}
+
+/// Group the slots by day and classify each day as free, partially free or busy, for each user and for the whole group.
+/// Slots must be sorted and share the same user list.
+pub fn free_days(slots: &[SlotAvailability], options: &FreeDaysOptions) -> Vec<FreeDay> {
+    let mut days = vec![];
+    let mut first = 0;
+    while first < slots.len() {
+        let day_start = (slots[first].start + options.utc_offset).div_euclid(ONE_DAY_MS) * ONE_DAY_MS - options.utc_offset;
+        let mut last = first;
+        while last < slots.len() && slots[last].start < day_start + ONE_DAY_MS {
+            last += 1;
+        }
+        let day = &slots[first..last];
+        first = last;
+
+        let users = day[0].users.iter().enumerate().map(|(index, user)| {
+            let free = day.iter().filter(|slot| slot.users[index].presence >= options.threshold).count();
+            let free_ratio = free as f32 / day.len() as f32;
+            UserDay {
+                user: user.user.clone(),
+                status: options.status(free_ratio),
+                free_ratio,
+            }
+        }).collect();
+        let free = day.iter().filter(|slot| slot.users.iter().all(|user| user.presence >= options.threshold)).count();
+        let free_ratio = free as f32 / day.len() as f32;
+        days.push(FreeDay {
+            date: DateTime::from_timestamp_millis(day_start + options.utc_offset).unwrap_or_default().format("%Y-%m-%d").to_string(),
+            start: day_start,
+            status: options.status(free_ratio),
+            free_ratio,
+            users,
+        });
+    }
+    days
+}
@@ -0,0 +1,4 @@
+pub mod availability;
+pub mod free_days;
+pub mod recurrence;
+pub mod suggest;
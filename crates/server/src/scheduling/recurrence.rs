@@ -0,0 +1,433 @@
+use crate::database::event::Event;
+use anyhow::Error;
+use chrono::{DateTime, Datelike, Duration, LocalResult, Months, NaiveDate, NaiveDateTime, TimeZone, Utc, Weekday};
+use chrono_tz::Tz;
+use serde::Serialize;
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+
+/// Stop expanding rules after this many occurrences to protect against unbounded rules
+const MAX_OCCURRENCES: usize = 10000;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Frequency {
+    Daily,
+    Weekly,
+    Monthly,
+}
+
+/// A weekday, with an optional ordinal inside the month (ex: 2TU, -1FR)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ByDay {
+    pub ordinal: Option<i32>,
+    pub weekday: Weekday,
+}
+
+/// Subset of the RFC 5545 RRULE : FREQ=DAILY/WEEKLY/MONTHLY, INTERVAL, BYDAY, COUNT and UNTIL.
+/// Occurrences are computed in the local time of the series, so they keep the same hour across daylight saving changes.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Recurrence {
+    pub frequency: Frequency,
+    pub interval: u32,
+    pub by_day: Vec<ByDay>,
+    pub count: Option<u32>,
+    /// Inclusive limit, timestamp in ms
+    pub until: Option<i64>,
+}
+
+impl FromStr for Recurrence {
+    type Err = Error;
+
+    fn from_str(rule: &str) -> Result<Self, Self::Err> {
+        let rule = rule.trim();
+        let rule = rule.strip_prefix("RRULE:").unwrap_or(rule);
+        let mut frequency = None;
+        let mut recurrence = Recurrence {
+            frequency: Frequency::Daily,
+            interval: 1,
+            by_day: vec![],
+            count: None,
+            until: None,
+        };
+        for part in rule.split(';').filter(|part| !part.is_empty()) {
+            let (key, value) = part.split_once('=').ok_or(Error::msg(format!("Invalid recurrence rule part '{part}'")))?;
+            match key.to_uppercase().as_str() {
+                "FREQ" => frequency = Some(match value.to_uppercase().as_str() {
+                    "DAILY" => Frequency::Daily,
+                    "WEEKLY" => Frequency::Weekly,
+                    "MONTHLY" => Frequency::Monthly,
+                    other => return Err(Error::msg(format!("Unsupported recurrence frequency {other}"))),
+                }),
+                "INTERVAL" => {
+                    recurrence.interval = u32::from_str(value)?;
+                    if recurrence.interval == 0 {
+                        return Err(Error::msg("Recurrence interval cannot be 0"));
+                    }
+                }
+                "COUNT" => recurrence.count = Some(u32::from_str(value)?),
+                "UNTIL" => recurrence.until = Some(parse_utc_date_time(value)?),
+                "BYDAY" => {
+                    for day in value.split(',') {
+                        recurrence.by_day.push(parse_by_day(day)?);
+                    }
+                }
+                "WKST" => {}
+                other => return Err(Error::msg(format!("Unsupported recurrence rule part {other}"))),
+            }
+        }
+        recurrence.frequency = frequency.ok_or(Error::msg("Missing recurrence frequency"))?;
+        if recurrence.frequency != Frequency::Monthly && recurrence.by_day.iter().any(|day| day.ordinal.is_some()) {
+            return Err(Error::msg("BYDAY ordinals are only supported for monthly rules"));
+        }
+        Ok(recurrence)
+    }
+}
+
+impl Display for Recurrence {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self.frequency {
+            Frequency::Daily => "FREQ=DAILY",
+            Frequency::Weekly => "FREQ=WEEKLY",
+            Frequency::Monthly => "FREQ=MONTHLY",
+        })?;
+        if self.interval != 1 {
+            write!(f, ";INTERVAL={}", self.interval)?;
+        }
+        if !self.by_day.is_empty() {
+            let days: Vec<String> = self.by_day.iter().map(|day| {
+                format!("{}{}", day.ordinal.map(|ordinal| ordinal.to_string()).unwrap_or_default(), weekday_code(day.weekday))
+            }).collect();
+            write!(f, ";BYDAY={}", days.join(","))?;
+        }
+        if let Some(count) = self.count {
+            write!(f, ";COUNT={count}")?;
+        }
+        if let Some(until) = self.until {
+            write!(f, ";UNTIL={}", DateTime::<Utc>::from_timestamp_millis(until).unwrap_or_default().format("%Y%m%dT%H%M%SZ"))?;
+        }
+        Ok(())
+    }
+}
+
+impl Recurrence {
+    /// Start times (in ms) of the occurrences of a series starting at `start` in the given time zone that intersect [from, to[.
+    /// `duration` is the length of one occurrence. Excluded dates are still counted by COUNT as stated by RFC 5545.
+    pub fn occurrences(&self, start: i64, duration: i64, exdates: &[i64], timezone: &Tz, from: i64, to: i64) -> Vec<i64> {
+        let first = match DateTime::<Utc>::from_timestamp_millis(start) {
+            None => return vec![],
+            Some(first) => first.with_timezone(timezone).naive_local(),
+        };
+        let mut result = vec![];
+        let mut count = 0;
+        let mut period = 0;
+        loop {
+            let candidates = self.period_candidates(&first, period);
+            period += 1;
+            let Some(candidates) = candidates else {
+                break;
+            };
+            for candidate in candidates {
+                let Some(candidate) = local_timestamp(timezone, &candidate) else {
+                    continue;
+                };
+                if candidate < start {
+                    continue;
+                }
+                if candidate >= to || self.until.map(|until| candidate > until).unwrap_or(false) {
+                    return result;
+                }
+                count += 1;
+                if count > MAX_OCCURRENCES || self.count.map(|max| count > max as usize).unwrap_or(false) {
+                    return result;
+                }
+                if candidate + duration > from && !exdates.contains(&candidate) {
+                    result.push(candidate);
+                }
+            }
+            if period as usize > MAX_OCCURRENCES {
+                break;
+            }
+        }
+        result
+    }
+
+    /// Sorted candidate dates of the nth period (day, week or month) of the series
+    fn period_candidates(&self, first: &NaiveDateTime, period: u32) -> Option<Vec<NaiveDateTime>> {
+        let step = period.checked_mul(self.interval)?;
+        let time = first.time();
+        Some(match self.frequency {
+            Frequency::Daily => {
+                let date = first.date().checked_add_signed(Duration::days(step as i64))?;
+                if self.by_day.is_empty() || self.by_day.iter().any(|day| day.weekday == date.weekday()) {
+                    vec![date.and_time(time)]
+                } else {
+                    vec![]
+                }
+            }
+            Frequency::Weekly => {
+                let week_start = first.date().checked_sub_signed(Duration::days(first.weekday().num_days_from_monday() as i64))?
+                    .checked_add_signed(Duration::weeks(step as i64))?;
+                let mut days: Vec<u32> = if self.by_day.is_empty() {
+                    vec![first.weekday().num_days_from_monday()]
+                } else {
+                    self.by_day.iter().map(|day| day.weekday.num_days_from_monday()).collect()
+                };
+                days.sort();
+                days.dedup();
+                days.into_iter().map(|day| (week_start + Duration::days(day as i64)).and_time(time)).collect()
+            }
+            Frequency::Monthly => {
+                let month = NaiveDate::from_ymd_opt(first.year(), first.month(), 1)?.checked_add_months(Months::new(step))?;
+                let mut dates = vec![];
+                if self.by_day.is_empty() {
+                    // Months without this day are skipped
+                    if let Some(date) = month.with_day(first.day()) {
+                        dates.push(date);
+                    }
+                } else {
+                    for by_day in &self.by_day {
+                        let matching = month.iter_days()
+                            .take_while(|date| date.month() == month.month())
+                            .filter(|date| date.weekday() == by_day.weekday)
+                            .collect::<Vec<NaiveDate>>();
+                        match by_day.ordinal {
+                            None => dates.extend(matching),
+                            Some(ordinal) if ordinal > 0 => dates.extend(matching.get(ordinal as usize - 1)),
+                            Some(ordinal) => dates.extend(matching.len().checked_sub(ordinal.unsigned_abs() as usize).and_then(|index| matching.get(index))),
+                        }
+                    }
+                    dates.sort();
+                    dates.dedup();
+                }
+                dates.into_iter().map(|date| date.and_time(time)).collect()
+            }
+        })
+    }
+}
+
+/// Timestamp in ms of a local time. Times skipped by a daylight saving change are moved forward by one hour,
+/// and repeated times resolve to their first instance (RFC 5545 3.3.5).
+fn local_timestamp(timezone: &Tz, time: &NaiveDateTime) -> Option<i64> {
+    match timezone.from_local_datetime(time) {
+        LocalResult::Single(time) | LocalResult::Ambiguous(time, _) => Some(time.timestamp_millis()),
+        LocalResult::None => timezone.from_local_datetime(&time.checked_add_signed(Duration::hours(1))?).earliest().map(|time| time.timestamp_millis()),
+    }
+}
+
+/// Time zone in which the occurrences of a recurring event are computed
+pub fn series_timezone(event: &Event) -> Tz {
+    event.timezone.as_deref().and_then(|timezone| Tz::from_str(timezone).ok()).unwrap_or(Tz::UTC)
+}
+
+/// An event as sent to the clients. Occurrences of a recurring event carry their own start and end times, but keep the id
+/// of their series : editing or deleting this id applies to the whole series.
+#[derive(Serialize, Debug, Clone)]
+pub struct EventOccurrence {
+    #[serde(flatten)]
+    pub event: Event,
+    /// Start and end times of the whole series, for recurring events only
+    pub series: Option<SeriesTimes>,
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+pub struct SeriesTimes {
+    pub start_time: i64,
+    pub end_time: i64,
+}
+
+/// List the occurrences of the events intersecting [from, to[. Events that are not recurring are returned as is.
+pub fn expand_occurrences(events: Vec<Event>, from: i64, to: i64) -> Vec<EventOccurrence> {
+    let mut expanded = vec![];
+    for event in events {
+        let recurrence = match event.recurrence.as_deref().map(Recurrence::from_str) {
+            Some(Ok(recurrence)) => recurrence,
+            _ => {
+                expanded.push(EventOccurrence { event, series: None });
+                continue;
+            }
+        };
+        let series = SeriesTimes { start_time: event.start_time, end_time: event.end_time };
+        let duration = event.end_time - event.start_time;
+        for start in recurrence.occurrences(event.start_time, duration, &event.exdates, &series_timezone(&event), from, to) {
+            let mut occurrence = event.clone();
+            occurrence.start_time = start;
+            occurrence.end_time = start + duration;
+            expanded.push(EventOccurrence { event: occurrence, series: Some(series) });
+        }
+    }
+    expanded
+}
+
+/// Replace recurring events with their occurrences intersecting [from, to[. Occurrences keep the id of their series,
+/// so the result is only meant for computations and should not be sent to the clients.
+pub fn expand_events(events: Vec<Event>, from: i64, to: i64) -> Vec<Event> {
+    expand_occurrences(events, from, to).into_iter().map(|occurrence| occurrence.event).collect()
+}
+
+fn parse_utc_date_time(value: &str) -> Result<i64, Error> {
+    let value = value.trim_end_matches('Z');
+    let date_time = if value.len() == 8 {
+        NaiveDate::parse_from_str(value, "%Y%m%d")?.and_hms_opt(23, 59, 59).ok_or(Error::msg("Invalid date"))?
+    } else {
+        NaiveDateTime::parse_from_str(value, "%Y%m%dT%H%M%S")?
+    };
+    Ok(date_time.and_utc().timestamp_millis())
+}
+
+fn parse_by_day(value: &str) -> Result<ByDay, Error> {
+    let value = value.trim().to_uppercase();
+    // The weekday code is split on a byte offset below
+    if value.len() < 2 || !value.is_ascii() {
+        return Err(Error::msg(format!("Invalid BYDAY value {value}")));
+    }
+    let (ordinal, code) = value.split_at(value.len() - 2);
+    let weekday = match code {
+        "MO" => Weekday::Mon,
+        "TU" => Weekday::Tue,
+        "WE" => Weekday::Wed,
+        "TH" => Weekday::Thu,
+        "FR" => Weekday::Fri,
+        "SA" => Weekday::Sat,
+        "SU" => Weekday::Sun,
+        _ => return Err(Error::msg(format!("Invalid BYDAY value {value}"))),
+    };
+    let ordinal = if ordinal.is_empty() {
+        None
+    } else {
+        let ordinal = i32::from_str(ordinal.trim_start_matches('+'))?;
+        if ordinal == 0 || ordinal.abs() > 5 {
+            return Err(Error::msg(format!("Invalid BYDAY ordinal {ordinal}")));
+        }
+        Some(ordinal)
+    };
+    Ok(ByDay { ordinal, weekday })
+}
+
+fn weekday_code(weekday: Weekday) -> &'static str {
+    match weekday {
+        Weekday::Mon => "MO",
+        Weekday::Tue => "TU",
+        Weekday::Wed => "WE",
+        Weekday::Thu => "TH",
+        Weekday::Fri => "FR",
+        Weekday::Sat => "SA",
+        Weekday::Sun => "SU",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HOUR: i64 = 60 * 60 * 1000;
+
+    fn time(year: i32, month: u32, day: u32, hour: u32) -> i64 {
+        NaiveDate::from_ymd_opt(year, month, day).unwrap().and_hms_opt(hour, 0, 0).unwrap().and_utc().timestamp_millis()
+    }
+
+    #[test]
+    fn occurrences() {
+        // (rule, series start, excluded dates, range end, expected occurrences)
+        let cases = [
+            ("FREQ=MONTHLY;BYDAY=-1FR", time(2024, 1, 26, 10), vec![], time(2024, 5, 1, 0),
+             vec![time(2024, 1, 26, 10), time(2024, 2, 23, 10), time(2024, 3, 29, 10), time(2024, 4, 26, 10)]),
+            ("FREQ=MONTHLY;BYDAY=2TU", time(2024, 1, 9, 10), vec![], time(2024, 4, 1, 0),
+             vec![time(2024, 1, 9, 10), time(2024, 2, 13, 10), time(2024, 3, 12, 10)]),
+            ("FREQ=MONTHLY;BYDAY=+1MO,-1MO;COUNT=4", time(2024, 1, 1, 10), vec![], time(2025, 1, 1, 0),
+             vec![time(2024, 1, 1, 10), time(2024, 1, 29, 10), time(2024, 2, 5, 10), time(2024, 2, 26, 10)]),
+            // Excluded dates are still counted by COUNT
+            ("FREQ=DAILY;COUNT=5", time(2024, 1, 1, 10), vec![time(2024, 1, 3, 10)], time(2025, 1, 1, 0),
+             vec![time(2024, 1, 1, 10), time(2024, 1, 2, 10), time(2024, 1, 4, 10), time(2024, 1, 5, 10)]),
+            // A date-only UNTIL includes the whole day
+            ("FREQ=DAILY;UNTIL=20240103", time(2024, 1, 1, 10), vec![], time(2025, 1, 1, 0),
+             vec![time(2024, 1, 1, 10), time(2024, 1, 2, 10), time(2024, 1, 3, 10)]),
+            ("FREQ=DAILY;UNTIL=20240103T090000Z", time(2024, 1, 1, 10), vec![], time(2025, 1, 1, 0),
+             vec![time(2024, 1, 1, 10), time(2024, 1, 2, 10)]),
+            // Months without a 31st are skipped
+            ("FREQ=MONTHLY", time(2024, 1, 31, 10), vec![], time(2024, 6, 1, 0),
+             vec![time(2024, 1, 31, 10), time(2024, 3, 31, 10), time(2024, 5, 31, 10)]),
+            ("FREQ=WEEKLY;BYDAY=MO,WE;COUNT=4", time(2024, 1, 1, 10), vec![], time(2025, 1, 1, 0),
+             vec![time(2024, 1, 1, 10), time(2024, 1, 3, 10), time(2024, 1, 8, 10), time(2024, 1, 10, 10)]),
+            ("FREQ=DAILY;INTERVAL=3", time(2024, 1, 1, 10), vec![], time(2024, 1, 8, 0),
+             vec![time(2024, 1, 1, 10), time(2024, 1, 4, 10), time(2024, 1, 7, 10)]),
+        ];
+        for (rule, start, exdates, to, expected) in cases {
+            let recurrence = Recurrence::from_str(rule).unwrap();
+            assert_eq!(recurrence.occurrences(start, HOUR, &exdates, &Tz::UTC, start, to), expected, "{rule}");
+        }
+    }
+
+    #[test]
+    fn occurrences_intersecting_range() {
+        let recurrence = Recurrence::from_str("FREQ=DAILY").unwrap();
+        // The occurrence of the 2nd started before the range but ends inside it
+        let occurrences = recurrence.occurrences(time(2024, 1, 1, 10), 2 * 60 * 60 * 1000, &[], &Tz::UTC, time(2024, 1, 2, 11), time(2024, 1, 4, 0));
+        assert_eq!(occurrences, vec![time(2024, 1, 2, 10), time(2024, 1, 3, 10)]);
+    }
+
+    #[test]
+    fn occurrences_in_local_time() {
+        // Every Tuesday at 9:00 in Paris : 8:00 UTC in winter, 7:00 UTC once daylight saving time starts on the 31st of March
+        let recurrence = Recurrence::from_str("FREQ=WEEKLY;BYDAY=TU;COUNT=3").unwrap();
+        let occurrences = recurrence.occurrences(time(2024, 3, 19, 8), HOUR, &[time(2024, 3, 26, 8)], &Tz::Europe__Paris, 0, time(2025, 1, 1, 0));
+        assert_eq!(occurrences, vec![time(2024, 3, 19, 8), time(2024, 4, 2, 7)]);
+
+        // Mondays at 8:00 in Auckland are still Sundays in UTC
+        let recurrence = Recurrence::from_str("FREQ=WEEKLY;BYDAY=MO;COUNT=2").unwrap();
+        let occurrences = recurrence.occurrences(time(2024, 6, 2, 20), HOUR, &[], &Tz::Pacific__Auckland, 0, time(2025, 1, 1, 0));
+        assert_eq!(occurrences, vec![time(2024, 6, 2, 20), time(2024, 6, 9, 20)]);
+
+        // 2:30 does not exist in Paris on the 31st of March and is moved to 3:30 local time
+        let recurrence = Recurrence::from_str("FREQ=DAILY;COUNT=3").unwrap();
+        let occurrences = recurrence.occurrences(time(2024, 3, 30, 1) + 30 * 60 * 1000, HOUR, &[], &Tz::Europe__Paris, 0, time(2025, 1, 1, 0));
+        assert_eq!(occurrences, vec![time(2024, 3, 30, 1) + 30 * 60 * 1000, time(2024, 3, 31, 1) + 30 * 60 * 1000, time(2024, 4, 1, 0) + 30 * 60 * 1000]);
+    }
+
+    #[test]
+    fn parse_rules() {
+        let valid = [
+            ("FREQ=WEEKLY;BYDAY=TU,TH", "FREQ=WEEKLY;BYDAY=TU,TH"),
+            ("RRULE:freq=monthly;byday=-1fr;count=3", "FREQ=MONTHLY;BYDAY=-1FR;COUNT=3"),
+            ("FREQ=DAILY;INTERVAL=2;UNTIL=20240103T100000Z;WKST=MO", "FREQ=DAILY;INTERVAL=2;UNTIL=20240103T100000Z"),
+        ];
+        for (rule, formatted) in valid {
+            assert_eq!(Recurrence::from_str(rule).unwrap().to_string(), formatted, "{rule}");
+        }
+
+        let invalid = [
+            "BYDAY=MO",
+            "FREQ=YEARLY",
+            "FREQ=DAILY;INTERVAL=0",
+            "FREQ=WEEKLY;BYDAY=1MO",
+            "FREQ=MONTHLY;BYDAY=6MO",
+            "FREQ=MONTHLY;BYDAY=0MO",
+            "FREQ=MONTHLY;BYDAY=XX",
+            "FREQ=MONTHLY;BYDAY=1É",
+            "FREQ=MONTHLY;BYDAY=É",
+            "FREQ=MONTHLY;BYDAY=🗓️",
+            "FREQ=DAILY;BYSETPOS=1",
+        ];
+        for rule in invalid {
+            assert!(Recurrence::from_str(rule).is_err(), "{rule}");
+        }
+    }
+
+    #[test]
+    fn expanded_occurrences_keep_their_series() {
+        let mut series = Event::default();
+        series.start_time = time(2024, 1, 1, 10);
+        series.end_time = time(2024, 1, 1, 11);
+        series.recurrence = Some(String::from("FREQ=DAILY;COUNT=3"));
+        let mut single = Event::default();
+        single.start_time = time(2024, 1, 2, 15);
+        single.end_time = time(2024, 1, 2, 16);
+
+        let occurrences = expand_occurrences(vec![series, single], time(2024, 1, 1, 0), time(2024, 2, 1, 0));
+        let starts: Vec<i64> = occurrences.iter().map(|occurrence| occurrence.event.start_time).collect();
+        assert_eq!(starts, vec![time(2024, 1, 1, 10), time(2024, 1, 2, 10), time(2024, 1, 3, 10), time(2024, 1, 2, 15)]);
+        assert_eq!(occurrences[2].event.end_time, time(2024, 1, 3, 11));
+        let series = SeriesTimes { start_time: time(2024, 1, 1, 10), end_time: time(2024, 1, 1, 11) };
+        assert!(occurrences[..3].iter().all(|occurrence| occurrence.series == Some(series)));
+        assert_eq!(occurrences[3].series, None);
+    }
+}
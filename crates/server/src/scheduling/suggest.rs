@@ -0,0 +1,75 @@
+use crate::scheduling::availability::SlotAvailability;
+use crate::types::database_ids::CalendarUserId;
+use serde::Serialize;
+
+pub struct SuggestOptions {
+    /// Length of the requested window in ms
+    pub duration: i64,
+    /// Maximum number of returned windows
+    pub count: usize,
+    /// Minimum number of users that should be present during the whole window
+    pub min_participants: usize,
+    /// Users that must be present during the whole window
+    pub required: Vec<CalendarUserId>,
+    /// A user is considered present in a slot if its presence is greater or equal to this threshold
+    pub threshold: f32,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct Suggestion {
+    pub start: i64,
+    pub end: i64,
+    pub presence: f32,
+    pub participants: Vec<CalendarUserId>,
+}
+
+/// Find the best non-overlapping windows of the requested duration, ranked by number of participants then by mean presence.
+/// Slots must be sorted and share the same user list.
+pub fn suggest_windows(slots: &[SlotAvailability], options: &SuggestOptions) -> Vec<Suggestion> {
+    let mut candidates = vec![];
+    for first in 0..slots.len() {
+        let mut last = first;
+        while slots[last].end - slots[first].start < options.duration {
+            if last + 1 >= slots.len() || slots[last + 1].start != slots[last].end {
+                break;
+            }
+            last += 1;
+        }
+        if slots[last].end - slots[first].start < options.duration {
+            continue;
+        }
+
+        let window = &slots[first..=last];
+        let participants: Vec<CalendarUserId> = window[0].users.iter().enumerate()
+            .filter(|(index, _)| window.iter().all(|slot| slot.users[*index].presence >= options.threshold))
+            .map(|(_, user)| user.user.clone())
+            .collect();
+        if participants.len() < options.min_participants || !options.required.iter().all(|user| participants.contains(user)) {
+            continue;
+        }
+
+        candidates.push(Suggestion {
+            start: window[0].start,
+            end: window[window.len() - 1].end,
+            presence: window.iter().map(|slot| slot.presence).sum::<f32>() / window.len() as f32,
+            participants,
+        });
+    }
+
+    candidates.sort_by(|a, b| {
+        b.participants.len().cmp(&a.participants.len())
+            .then(b.presence.total_cmp(&a.presence))
+            .then(a.start.cmp(&b.start))
+    });
+
+    let mut suggestions: Vec<Suggestion> = vec![];
+    for candidate in candidates {
+        if suggestions.len() >= options.count {
+            break;
+        }
+        if suggestions.iter().all(|selected| candidate.end <= selected.start || candidate.start >= selected.end) {
+            suggestions.push(candidate);
+        }
+    }
+    suggestions
+}
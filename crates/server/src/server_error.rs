@@ -1,26 +1,44 @@
-use axum::http::StatusCode;
+use axum::http::{header, StatusCode};
 use axum::response::{IntoResponse, Response};
+use std::time::Duration;
 
-pub struct ServerError((StatusCode, anyhow::Error));
+pub struct ServerError {
+    error: (StatusCode, anyhow::Error),
+    /// Delay in seconds sent in the Retry-After header
+    retry_after: Option<u64>,
+}
 
 impl ServerError {
     pub fn error<E: Into<anyhow::Error>>(code: StatusCode, msg: E) -> Self {
-        Self((code, msg.into()))
+        Self { error: (code, msg.into()), retry_after: None }
     }
 
     pub fn msg<E>(code: StatusCode, msg: E) -> Self
     where E: std::fmt::Display + std::fmt::Debug + Send + Sync + 'static {
-        Self((code, anyhow::Error::msg(msg)))
+        Self { error: (code, anyhow::Error::msg(msg)), retry_after: None }
+    }
+
+    pub fn too_many_requests(retry_after: Duration) -> Self {
+        // Round up to never tell the client to retry too early
+        let retry_after = retry_after.as_secs() + if retry_after.subsec_nanos() > 0 { 1 } else { 0 };
+        Self {
+            error: (StatusCode::TOO_MANY_REQUESTS, anyhow::Error::msg(format!("Too many requests, retry in {retry_after}s"))),
+            retry_after: Some(retry_after),
+        }
     }
 }
 
 
 impl IntoResponse for ServerError {
     fn into_response(self) -> Response {
-        (
-            self.0.0,
-            format!("{}: {}", self.0.0.as_str(), self.0.1),
-        ).into_response()
+        let mut response = (
+            self.error.0,
+            format!("{}: {}", self.error.0.as_str(), self.error.1),
+        ).into_response();
+        if let Some(retry_after) = self.retry_after {
+            response.headers_mut().insert(header::RETRY_AFTER, retry_after.into());
+        }
+        response
     }
 }
 
@@ -29,6 +47,6 @@ where
     E: Into<anyhow::Error>,
 {
     fn from(err: E) -> Self {
-        Self((StatusCode::INTERNAL_SERVER_ERROR, err.into()))
+        Self { error: (StatusCode::INTERNAL_SERVER_ERROR, err.into()), retry_after: None }
     }
-}
\ No newline at end of file
+}
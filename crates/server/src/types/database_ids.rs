@@ -108,6 +108,8 @@ make_database_id!(CalendarUserId);
 make_database_id!(UserId);
 make_database_id!(EventId);
 make_database_id!(CalendarId);
+make_database_id!(AuthTokenId);
+make_database_id!(ShareLinkId);
 
 make_wrapped_db_type!(PasswordHash, String, Clone, Default, Debug, serde::Serialize, serde::Deserialize);
 impl PasswordHash {
@@ -0,0 +1,3 @@
+ALTER TABLE SCHEMA_NAME.events
+        DROP COLUMN IF EXISTS recurrence,
+        DROP COLUMN IF EXISTS exdates;
\ No newline at end of file
@@ -0,0 +1,3 @@
+ALTER TABLE SCHEMA_NAME.events
+        ADD COLUMN IF NOT EXISTS recurrence VARCHAR(500),
+        ADD COLUMN IF NOT EXISTS exdates BIGINT[] NOT NULL DEFAULT '{}';
\ No newline at end of file
@@ -0,0 +1,2 @@
+ALTER TABLE SCHEMA_NAME.authtoken
+        DROP COLUMN IF EXISTS lifetime;
\ No newline at end of file
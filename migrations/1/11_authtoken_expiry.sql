@@ -0,0 +1,5 @@
+ALTER TABLE SCHEMA_NAME.authtoken
+        ADD COLUMN IF NOT EXISTS lifetime BIGINT NOT NULL DEFAULT 0;
+
+-- Tokens created before this migration were stored with an expiration date equal to their creation date
+UPDATE SCHEMA_NAME.authtoken SET lifetime = 2592000, expdate = expdate + 2592000 WHERE lifetime = 0;
\ No newline at end of file
@@ -0,0 +1,5 @@
+ALTER TABLE SCHEMA_NAME.authtoken
+        DROP COLUMN IF EXISTS id,
+        DROP COLUMN IF EXISTS created_at,
+        DROP COLUMN IF EXISTS last_used,
+        DROP COLUMN IF EXISTS ip;
\ No newline at end of file
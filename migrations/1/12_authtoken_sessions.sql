@@ -0,0 +1,5 @@
+ALTER TABLE SCHEMA_NAME.authtoken
+        ADD COLUMN IF NOT EXISTS id BIGSERIAL UNIQUE,
+        ADD COLUMN IF NOT EXISTS created_at BIGINT NOT NULL DEFAULT 0,
+        ADD COLUMN IF NOT EXISTS last_used BIGINT NOT NULL DEFAULT 0,
+        ADD COLUMN IF NOT EXISTS ip VARCHAR(64);
\ No newline at end of file
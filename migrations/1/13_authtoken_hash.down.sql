@@ -0,0 +1,4 @@
+-- Hashed tokens can't be recovered : every session is closed
+DELETE FROM SCHEMA_NAME.authtoken;
+
+ALTER TABLE SCHEMA_NAME.authtoken RENAME COLUMN token_hash TO token;
\ No newline at end of file
@@ -0,0 +1,4 @@
+ALTER TABLE SCHEMA_NAME.authtoken RENAME COLUMN token TO token_hash;
+
+-- Existing tokens are hashed in place, so opened sessions stay valid
+UPDATE SCHEMA_NAME.authtoken SET token_hash = encode(sha256(convert_to(token_hash, 'UTF8')), 'hex');
\ No newline at end of file
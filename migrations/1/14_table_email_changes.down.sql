@@ -0,0 +1 @@
+DROP TABLE IF EXISTS SCHEMA_NAME.email_changes;
\ No newline at end of file
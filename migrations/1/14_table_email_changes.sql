@@ -0,0 +1,7 @@
+CREATE TABLE IF NOT EXISTS SCHEMA_NAME.email_changes (
+    user_id BIGINT PRIMARY KEY,
+    new_email VARCHAR(200) NOT NULL,
+    code VARCHAR(8) NOT NULL,
+    expdate BIGINT NOT NULL,
+    FOREIGN KEY(user_id) REFERENCES SCHEMA_NAME.users(id)
+);
\ No newline at end of file
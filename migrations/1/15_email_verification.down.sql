@@ -0,0 +1,4 @@
+DROP TABLE IF EXISTS SCHEMA_NAME.email_verifications;
+
+ALTER TABLE SCHEMA_NAME.users
+        DROP COLUMN IF EXISTS email_verified;
\ No newline at end of file
@@ -0,0 +1,13 @@
+-- Accounts created before this migration are considered verified
+ALTER TABLE SCHEMA_NAME.users
+        ADD COLUMN IF NOT EXISTS email_verified BOOLEAN NOT NULL DEFAULT TRUE;
+ALTER TABLE SCHEMA_NAME.users
+        ALTER COLUMN email_verified SET DEFAULT FALSE;
+
+CREATE TABLE IF NOT EXISTS SCHEMA_NAME.email_verifications (
+    user_id BIGINT PRIMARY KEY,
+    code VARCHAR(8) NOT NULL,
+    expdate BIGINT NOT NULL,
+    sent_at BIGINT NOT NULL,
+    FOREIGN KEY(user_id) REFERENCES SCHEMA_NAME.users(id)
+);
\ No newline at end of file
@@ -0,0 +1,2 @@
+ALTER TABLE SCHEMA_NAME.resetpasswords
+        DROP COLUMN IF EXISTS attempts;
\ No newline at end of file
@@ -0,0 +1,2 @@
+ALTER TABLE SCHEMA_NAME.resetpasswords
+        ADD COLUMN IF NOT EXISTS attempts INTEGER NOT NULL DEFAULT 0;
\ No newline at end of file
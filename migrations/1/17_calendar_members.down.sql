@@ -0,0 +1,2 @@
+ALTER TABLE SCHEMA_NAME.calendars DROP COLUMN IF EXISTS default_role;
+DROP TABLE IF EXISTS SCHEMA_NAME.calendar_members;
\ No newline at end of file
@@ -0,0 +1,15 @@
+CREATE TABLE IF NOT EXISTS SCHEMA_NAME.calendar_members (
+    calendar_id BIGINT NOT NULL,
+    user_id BIGINT NOT NULL,
+    role VARCHAR(16) NOT NULL,
+    PRIMARY KEY (calendar_id, user_id),
+    FOREIGN KEY(calendar_id) REFERENCES SCHEMA_NAME.calendars(id),
+    FOREIGN KEY(user_id) REFERENCES SCHEMA_NAME.users(id)
+);
+
+-- Role given to visitors that are not members. Existing calendars keep letting anyone participate.
+ALTER TABLE SCHEMA_NAME.calendars ADD COLUMN IF NOT EXISTS default_role VARCHAR(16) NOT NULL DEFAULT 'participant';
+
+INSERT INTO SCHEMA_NAME.calendar_members (calendar_id, user_id, role)
+    SELECT id, owner_id, 'owner' FROM SCHEMA_NAME.calendars
+    ON CONFLICT DO NOTHING;
\ No newline at end of file
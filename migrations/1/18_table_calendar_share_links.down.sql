@@ -0,0 +1 @@
+DROP TABLE IF EXISTS SCHEMA_NAME.calendar_share_links;
\ No newline at end of file
@@ -0,0 +1,12 @@
+CREATE TABLE IF NOT EXISTS SCHEMA_NAME.calendar_share_links (
+    id BIGSERIAL PRIMARY KEY,
+    calendar_id BIGINT NOT NULL,
+    token CHAR(24) NOT NULL UNIQUE,
+    access_key CHAR(32) NOT NULL UNIQUE,
+    scope VARCHAR(16) NOT NULL,
+    expdate BIGINT,
+    password_hash VARCHAR(255),
+    uses BIGINT NOT NULL DEFAULT 0,
+    created_at BIGINT NOT NULL,
+    FOREIGN KEY(calendar_id) REFERENCES SCHEMA_NAME.calendars(id)
+);
\ No newline at end of file
@@ -0,0 +1 @@
+DROP INDEX IF EXISTS SCHEMA_NAME.calendar_users_account;
\ No newline at end of file
@@ -0,0 +1,9 @@
+-- An account can only control one calendar user per calendar : the oldest one is kept, the others become anonymous
+UPDATE SCHEMA_NAME.calendar_users duplicate SET user_id = NULL
+    WHERE user_id IS NOT NULL AND id > (
+        SELECT MIN(id) FROM SCHEMA_NAME.calendar_users kept
+        WHERE kept.calendar_id = duplicate.calendar_id AND kept.user_id = duplicate.user_id
+    );
+
+CREATE UNIQUE INDEX IF NOT EXISTS calendar_users_account
+    ON SCHEMA_NAME.calendar_users (calendar_id, user_id) WHERE user_id IS NOT NULL;
\ No newline at end of file
@@ -0,0 +1,2 @@
+DROP TABLE IF EXISTS SCHEMA_NAME.calendar_user_claims;
+ALTER TABLE SCHEMA_NAME.calendar_users DROP COLUMN IF EXISTS claim_hash;
\ No newline at end of file
@@ -0,0 +1,11 @@
+ALTER TABLE SCHEMA_NAME.calendar_users ADD COLUMN IF NOT EXISTS claim_hash CHAR(64);
+
+-- Accounts asking the calendar owner to let them control an anonymous calendar user
+CREATE TABLE IF NOT EXISTS SCHEMA_NAME.calendar_user_claims (
+    calendar_user_id BIGINT NOT NULL,
+    user_id BIGINT NOT NULL,
+    created_at BIGINT NOT NULL,
+    PRIMARY KEY (calendar_user_id, user_id),
+    FOREIGN KEY(calendar_user_id) REFERENCES SCHEMA_NAME.calendar_users(id),
+    FOREIGN KEY(user_id) REFERENCES SCHEMA_NAME.users(id)
+);
\ No newline at end of file
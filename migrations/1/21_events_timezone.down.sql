@@ -0,0 +1,2 @@
+ALTER TABLE SCHEMA_NAME.events
+        DROP COLUMN IF EXISTS timezone;
\ No newline at end of file
@@ -0,0 +1,2 @@
+ALTER TABLE SCHEMA_NAME.events
+        ADD COLUMN IF NOT EXISTS timezone VARCHAR(64);
\ No newline at end of file
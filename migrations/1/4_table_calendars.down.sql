@@ -0,0 +1 @@
+DROP TABLE IF EXISTS SCHEMA_NAME.calendars;
\ No newline at end of file
@@ -0,0 +1 @@
+DROP TABLE IF EXISTS SCHEMA_NAME.calendar_users;
\ No newline at end of file
@@ -0,0 +1 @@
+DROP TABLE IF EXISTS SCHEMA_NAME.authtoken;
\ No newline at end of file
@@ -0,0 +1 @@
+DROP TABLE IF EXISTS SCHEMA_NAME.resetpasswords;
\ No newline at end of file
@@ -0,0 +1 @@
+DROP TABLE IF EXISTS SCHEMA_NAME.calendar_feeds;
\ No newline at end of file
@@ -0,0 +1,5 @@
+CREATE TABLE IF NOT EXISTS SCHEMA_NAME.calendar_feeds (
+        calendar_id BIGINT PRIMARY KEY,
+        token CHAR(32) NOT NULL UNIQUE,
+        FOREIGN KEY(calendar_id) REFERENCES SCHEMA_NAME.calendars(id)
+    );
\ No newline at end of file
@@ -278,18 +278,31 @@ class CalendarApp extends HTMLElement {
                             file_input.onchange = async (event) => {
 
                                 const user = (await this.get_connected_user()).id.toString();
+                                const calendar = APP_CONFIG.display_calendar();
                                 const body = [];
+                                let skipped = 0;
                                 for (const element of await import_ics(event.target['files'][0])) {
+                                    // The server rejects events outside of the calendar range : clip them, or skip them if nothing is left
+                                    const start = Math.max(element.start, calendar.start_date);
+                                    const end = Math.min(element.end, calendar.end_date);
+                                    if (end <= start) {
+                                        skipped += 1;
+                                        continue;
+                                    }
                                     body.push({
-                                        calendar: APP_CONFIG.display_calendar().id.toString(),
+                                        calendar: calendar.id.toString(),
                                         title: element.title,
                                         owner: user,
-                                        start: element.start,
-                                        end: element.end,
+                                        start: start,
+                                        end: end,
                                         source: element.source,
                                         presence: Number(-10)
                                     });
                                 }
+                                if (skipped > 0)
+                                    NOTIFICATION.warn(new Message(`${skipped} évenement(s) en dehors du calendrier n'ont pas été importés`).title("Import ICS"));
+                                if (body.length === 0)
+                                    return;
                                 const res = await fetch_api('event/create', 'POST', body).catch(error => {
                                     NOTIFICATION.error(new Message(error).title("Impossible de créer les évenements"));
                                     throw new Error(error);